@@ -0,0 +1,417 @@
+use crate::vec3::*;
+use crate::sampler::*;
+use crate::texture::*;
+use enum_dispatch::enum_dispatch;
+use std::f64::consts::PI;
+use std::path::Path;
+
+// what rays that leave the scene see
+#[enum_dispatch]
+pub trait Illuminate {
+    // light arriving from far away, seen looking along unit vector `dir`
+    fn radiance(&self, dir: &Vec3) -> Colour;
+
+    // whether the lights can pick directions towards it, rather than
+    // leaving it all to bsdf sampling
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    // a unit direction, the light arriving along it and the solid angle
+    // density it was picked with
+    fn sample(&self, _sampler: &mut Sampler) -> Option<(Vec3, Colour, f64)> {
+        None
+    }
+
+    // density with which sample picks unit vector `dir`
+    fn pdf(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+#[enum_dispatch(Illuminate)]
+pub enum Background {
+    Gradient,
+    Uniform,
+    Environment,
+    Sky
+}
+
+// blends with height, like a clear sky
+pub struct Gradient {
+    pub bottom: Colour,
+    pub top: Colour
+}
+
+pub struct Uniform {
+    pub radiance: Colour
+}
+
+// an equirectangular image around the scene, e.g. a studio hdri. the top
+// row is straight up and the middle column looks along +x
+pub struct Environment {
+    image: ImageTexture,
+    pub rotation: f64, // degrees about y
+    pub intensity: f64,
+    distribution: Distribution2D
+}
+
+// preetham et al. 1999's analytic daylight, from the sun's position and the
+// turbidity of the air (2 is very clear, 10 hazy), with the sun as a disk
+// half a degree across. below the horizon is flat ground lit by both.
+// radiance comes out in kcd/m^2, which `intensity` scales down to the
+// rest of the scene
+pub struct Sky {
+    sun: Vec3, // unit, towards the sun
+    sun_radiance: Colour,
+    zenith: [f64; 3], // Y, x and y straight up
+    perez: [[f64; 5]; 3], // coefficients for Y, x and y
+    ground: Colour,
+    pub intensity: f64,
+    sun_probability: f64, // of sample aiming at the sun, rather than the rest
+    distribution: Distribution2D // over the sky and ground, without the sun
+}
+
+// cosine of the sun's angular radius, 0.27 degrees
+const SUN_COS_RADIUS: f64 = 0.99998918;
+
+impl Illuminate for Gradient {
+    fn radiance(&self, dir: &Vec3) -> Colour {
+        let a = 0.5 * (dir.y() + 1.0);
+        (1.0 - a) * self.bottom + a * self.top
+    }
+}
+
+impl Illuminate for Uniform {
+    fn radiance(&self, _dir: &Vec3) -> Colour {
+        self.radiance
+    }
+}
+
+impl Illuminate for Environment {
+    fn radiance(&self, dir: &Vec3) -> Colour {
+        let (u, v) = equirect_uv(dir, self.rotation);
+        let x = ((u * self.image.width() as f64) as usize).min(self.image.width() - 1);
+        let y = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
+        self.intensity * self.image.pixel(x, y)
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, sampler: &mut Sampler) -> Option<(Vec3, Colour, f64)> {
+        let (u, v, pdf) = self.distribution.sample(sampler.next_2d());
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 { return None; }
+
+        let dir = equirect_direction(u, v, self.rotation);
+        Some((dir, self.radiance(&dir), pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        equirect_pdf(&self.distribution, dir, self.rotation)
+    }
+}
+
+impl Illuminate for Sky {
+    fn radiance(&self, dir: &Vec3) -> Colour {
+        let sun = if dot(dir, &self.sun) >= SUN_COS_RADIUS { self.sun_radiance } else { colour(0, 0, 0) };
+        self.intensity * (self.sky(dir) + sun)
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn sample(&self, sampler: &mut Sampler) -> Option<(Vec3, Colour, f64)> {
+        let dir = if sampler.next_1d() < self.sun_probability {
+            randvec_in_cone(&self.sun, SUN_COS_RADIUS, sampler)
+        } else {
+            let (u, v, _) = self.distribution.sample(sampler.next_2d());
+            equirect_direction(u, v, 0.0)
+        };
+
+        let pdf = self.pdf(&dir);
+        if pdf <= 0.0 { return None; }
+        Some((dir, self.radiance(&dir), pdf))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        let sun = if dot(dir, &self.sun) >= SUN_COS_RADIUS { 1.0 / (2.0 * PI * (1.0 - SUN_COS_RADIUS)) } else { 0.0 };
+        self.sun_probability * sun + (1.0 - self.sun_probability) * equirect_pdf(&self.distribution, dir, 0.0)
+    }
+}
+
+impl Environment {
+    pub fn new(image: ImageTexture, rotation: f64, intensity: f64) -> Self {
+        assert!(image.width() > 0 && image.height() > 0, "environment image is empty");
+
+        // pick pixels by brightness, less so near the poles where they
+        // cover less of the sphere
+        let (w, h) = (image.width(), image.height());
+        let func: Vec<Vec<f64>> = (0..h).map(|y| {
+            let sin_theta = (PI * (y as f64 + 0.5) / h as f64).sin();
+            (0..w).map(|x| image.pixel(x, y).luminance().max(0.0) * sin_theta).collect()
+        }).collect();
+
+        Self { image, rotation, intensity, distribution: Distribution2D::new(func) }
+    }
+
+    // radiance .hdr and openexr images are read as linear
+    pub fn load<P: AsRef<Path>>(path: P, rotation: f64, intensity: f64) -> Result<Self, image::ImageError> {
+        Ok(Self::new(ImageTexture::load(path)?, rotation, intensity))
+    }
+
+}
+
+impl Sky {
+    pub fn new(sun: Vec3, turbidity: f64, ground_albedo: Colour, intensity: f64) -> Self {
+        let sun = unit_vector(sun);
+        let t = turbidity;
+        let theta_s = sun.y().clamp(-1.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let th = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(th).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886]
+        ]);
+        let zenith_yc = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688]
+        ]);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+        ];
+
+        let mut sky = Self {
+            sun,
+            sun_radiance: sun_radiance(theta_s, t),
+            zenith: [zenith_y, zenith_x, zenith_yc],
+            perez,
+            ground: colour(0, 0, 0),
+            intensity,
+            sun_probability: 0.0,
+            distribution: Distribution2D::new(vec![vec![1.0]])
+        };
+
+        // light reaching the ground, tabulated over the sky
+        let (w, h) = (128, 64);
+        let cell = |x: usize, y: usize| equirect_direction((x as f64 + 0.5) / w as f64, (y as f64 + 0.5) / h as f64, 0.0);
+        let solid_angle = |y: usize| 2.0 * PI * PI * (PI * (y as f64 + 0.5) / h as f64).sin() / (w * h) as f64;
+        let mut irradiance = sky.sun_radiance * (2.0 * PI * (1.0 - SUN_COS_RADIUS)) * sun.y().max(0.0);
+        for y in 0..h / 2 {
+            for x in 0..w {
+                let dir = cell(x, y);
+                irradiance += sky.sky(&dir) * dir.y() * solid_angle(y);
+            }
+        }
+        sky.ground = ground_albedo * irradiance / PI;
+
+        // directions picked by brightness, with the sun picked separately
+        // in proportion to the light it gives
+        let func: Vec<Vec<f64>> = (0..h).map(|y| {
+            (0..w).map(|x| sky.sky(&cell(x, y)).luminance().max(0.0) * solid_angle(y)).collect()
+        }).collect();
+        let sky_power: f64 = func.iter().flatten().sum();
+        let sun_power = sky.sun_radiance.luminance() * 2.0 * PI * (1.0 - SUN_COS_RADIUS);
+        sky.sun_probability = if sun_power > 0.0 { (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9) } else { 0.0 };
+        sky.distribution = Distribution2D::new(func);
+
+        sky
+    }
+
+    // everything but the sun
+    fn sky(&self, dir: &Vec3) -> Colour {
+        if dir.y() <= 0.0 { return self.ground; }
+
+        let cos_theta = dir.y().max(1.0e-3);
+        let cos_gamma = dot(dir, &self.sun).clamp(-1.0, 1.0);
+        let theta_s = self.sun.y().clamp(-1.0, 1.0).acos();
+        let perez = |c: [f64; 5], cos_theta: f64, cos_gamma: f64| {
+            let gamma = cos_gamma.acos();
+            (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+        };
+
+        // relative to the zenith, for each of Y, x and y
+        let [lum, x, y]: [f64; 3] = std::array::from_fn(|i| {
+            self.zenith[i] * perez(self.perez[i], cos_theta, cos_gamma) / perez(self.perez[i], 1.0, theta_s.cos())
+        });
+        if y <= 0.0 { return colour(0, 0, 0); }
+        colour(x * lum / y, lum, (1.0 - x - y) * lum / y).xyz_to_rgb()
+    }
+}
+
+// light from the sun's disk after the air's rayleigh and aerosol
+// scattering have taken their share, at 680, 550 and 440 nm
+fn sun_radiance(theta_s: f64, turbidity: f64) -> Colour {
+    if theta_s >= PI / 2.0 { return colour(0, 0, 0); }
+
+    // kasten's relative optical mass, and angstrom's aerosol turbidity
+    let m = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let tau = |lambda: f64| (-0.008735 * m * lambda.powf(-4.08)).exp() * (-beta * m * lambda.powf(-1.3)).exp();
+
+    // about what reaches the top of the atmosphere, in kcd/m^2
+    1.9e6 * colour(tau(0.68), tau(0.55), tau(0.44))
+}
+
+// image coordinates of unit vector `dir` in an equirectangular map turned
+// `rotation` degrees about y, with v = 0 at the top
+fn equirect_uv(dir: &Vec3, rotation: f64) -> (f64, f64) {
+    let theta = dir.y().clamp(-1.0, 1.0).acos();
+    let phi = (-dir.z()).atan2(dir.x()) + PI - rotation.to_radians();
+    (phi.rem_euclid(2.0 * PI) / (2.0 * PI), theta / PI)
+}
+
+fn equirect_direction(u: f64, v: f64, rotation: f64) -> Vec3 {
+    let (theta, phi) = (PI * v, 2.0 * PI * u + rotation.to_radians());
+    vec3(-phi.cos() * theta.sin(), theta.cos(), phi.sin() * theta.sin())
+}
+
+// solid angle density of picking unit vector `dir` by picking image
+// coordinates from `distribution`. the image stretches over 2 pi by pi
+// radians, squashed by sin theta towards the poles
+fn equirect_pdf(distribution: &Distribution2D, dir: &Vec3, rotation: f64) -> f64 {
+    let (u, v) = equirect_uv(dir, rotation);
+    let sin_theta = (PI * v).sin();
+    if sin_theta <= 0.0 { return 0.0; }
+    distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+}
+
+// picks from [0, 1) in proportion to a step function
+struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf: Vec<f64> = std::iter::once(0.0).chain(func.iter().scan(0.0, |sum, f| { *sum += f / n; Some(*sum) })).collect();
+        let integral = cdf[cdf.len() - 1];
+
+        // nothing to go on, so uniformly
+        if integral <= 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() { *c = i as f64 / n; }
+        } else {
+            for c in cdf.iter_mut() { *c /= integral; }
+        }
+
+        Self { func, cdf, integral }
+    }
+
+    // a point, its density and the step it fell in
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        let i = self.cdf.partition_point(|c| *c <= u).clamp(1, n) - 1;
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        let pdf = if self.integral > 0.0 { self.func[i] / self.integral } else { 1.0 };
+        ((i as f64 + du) / n as f64, pdf, i)
+    }
+
+    fn pdf(&self, x: f64) -> f64 {
+        if self.integral <= 0.0 { return 1.0; }
+        let i = ((x * self.func.len() as f64) as usize).min(self.func.len() - 1);
+        self.func[i] / self.integral
+    }
+}
+
+// picks from the unit square in proportion to a step function over a grid,
+// a row first and then where along it
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    fn new(func: Vec<Vec<f64>>) -> Self {
+        let rows: Vec<Distribution1D> = func.into_iter().map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        Self { rows, marginal }
+    }
+
+    fn sample(&self, (u0, u1): (f64, f64)) -> (f64, f64, f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.rows[row].sample(u0);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(v) * self.rows[row].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // uneven weights, with empty cells and an empty row that nothing
+    // should land in
+    fn grid() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 2.0, 0.0, 4.0, 1.0, 0.5],
+            vec![0.0; 6],
+            vec![3.0, 0.0, 0.0, 0.0, 7.0, 2.0],
+            vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6],
+            vec![5.0, 5.0, 0.0, 5.0, 5.0, 5.0]
+        ]
+    }
+
+    #[test]
+    fn distribution_2d_samples_match_its_pdf() {
+        let func = grid();
+        let (rows, cols) = (func.len(), func[0].len());
+        let total: f64 = func.iter().flatten().sum();
+        let d = Distribution2D::new(func.clone());
+
+        let n = 400;
+        let mut counts = vec![vec![0usize; cols]; rows];
+        for a in 0..n {
+            for b in 0..n {
+                let (u, v, pdf) = d.sample(((a as f64 + 0.5) / n as f64, (b as f64 + 0.5) / n as f64));
+                assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                assert!((pdf - d.pdf(u, v)).abs() < 1.0e-9, "sampled with {pdf} but pdf says {}", d.pdf(u, v));
+
+                let (i, j) = ((v * rows as f64) as usize, (u * cols as f64) as usize);
+                counts[i][j] += 1;
+            }
+        }
+
+        // a cell's share of the samples is its share of the total, and
+        // its density is that share over its area
+        for i in 0..rows {
+            for j in 0..cols {
+                let share = func[i][j] / total;
+                let picked = counts[i][j] as f64 / (n * n) as f64;
+                assert!((picked - share).abs() < 1.0e-3, "cell ({i}, {j}) got {picked} not {share}");
+                let centre = ((j as f64 + 0.5) / cols as f64, (i as f64 + 0.5) / rows as f64);
+                assert!((d.pdf(centre.0, centre.1) - share * (rows * cols) as f64).abs() < 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_distribution_is_uniform() {
+        let d = Distribution2D::new(vec![vec![0.0; 4]; 3]);
+        let (u, v, pdf) = d.sample((0.3, 0.8));
+        assert!((u - 0.3).abs() < 1.0e-9 && (v - 0.8).abs() < 1.0e-9);
+        assert_eq!(pdf, 1.0);
+        assert_eq!(d.pdf(0.9, 0.1), 1.0);
+    }
+}
@@ -0,0 +1,476 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::camera::*;
+use crate::integrator::*;
+use crate::light::*;
+use crate::background::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+
+// bidirectional path tracing (Veach 1997, chapter 10). a subpath is traced
+// from the camera and another from a random point on a light, then every
+// prefix of one is joined to every prefix of the other. each way of
+// building the same path is weighted by the power heuristic. lights
+// infinitely far away and the background have no point to start from, so
+// they're only ever joined to the camera subpath
+pub struct BdptIntegrator {
+    pub max_depth: i32 // bounces, not counting the ends
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light, // start of a light subpath
+    Surface,
+    Background // where the camera subpath escaped, or a direction picked towards it
+}
+
+#[derive(Clone)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Point3,
+    rec: Option<HitRecord>, // None for the camera and lights with no size
+    light: Option<&'a Light>, // the light with no size a light subpath starts at
+    r_in: Ray, // the ray that arrived here, for surface vertices
+    beta: Colour, // throughput of the subpath up to and including here
+    delta: bool, // scattered by a mirror-like material
+    pdf_fwd: f64, // area density of reaching here from the subpath's start, per solid angle for the background
+    pdf_rev: f64, // and from the other end
+    light_pdf: f64, // area density of a light subpath starting here, or of picking the background's direction
+    lambda: Wavelengths // as the subpath carried them here, like beta
+}
+
+impl Integrate for BdptIntegrator {
+    // without the camera, paths can't be joined to it from the light side,
+    // so those strategies are left out of the weights
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.trace(r, scene, None, lambda, &mut Vec::new(), sampler)
+    }
+
+    fn sample(&self, r: &Ray, scene: &Scene, cam: &Camera, lambda: &mut Wavelengths, splats: &mut Vec<Splat>,
+              sampler: &mut Sampler) -> Colour {
+        self.trace(r, scene, Some(cam), lambda, splats, sampler)
+    }
+}
+
+impl BdptIntegrator {
+    fn trace(&self, r: &Ray, scene: &Scene, cam: Option<&Camera>, lambda: &mut Wavelengths, splats: &mut Vec<Splat>,
+             sampler: &mut Sampler) -> Colour {
+        let max_depth = self.max_depth.max(0) as usize;
+        let mut radiance = colour(0, 0, 0);
+
+        // each subpath takes its own copy of the wavelengths, so whether
+        // one was dispersed doesn't leak into the other
+        let mut camera_path = vec![Vertex::camera(r.ori(), *lambda)];
+        let pdf_dir = cam.map_or(1.0, |c| c.pdf_dir(r.ori(), r.at(1.0)));
+        if let Some((escaped, beta, pdf_dir)) = random_walk(scene, *r, colour(1, 1, 1), pdf_dir, max_depth + 2, &mut camera_path, sampler) {
+            let last = camera_path.last().unwrap();
+            let light_pdf = if last.kind == VertexKind::Camera || last.delta { 0.0 } else { scene.background.pdf(&unit_vector(escaped.dir())) };
+            camera_path.push(Vertex::background(escaped, beta, pdf_dir, light_pdf, last.lambda));
+        }
+
+        let mut light_path = Vec::new();
+        light_subpath(scene, max_depth + 1, &mut light_path, *lambda, sampler);
+
+        for (t, pt) in camera_path.iter().enumerate().take(max_depth + 1).skip(1) {
+            radiance += connect_distant(scene, pt);
+            radiance += connect_background(scene, &camera_path, t + 1, sampler);
+        }
+
+        // s == 1 picks its own point on a light, so it runs even when no
+        // light subpath could be started
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len().max(1) {
+                let depth = s + t;
+                if (s == 1 && t == 1) || depth < 2 || depth - 2 > max_depth { continue; }
+
+                if t == 1 {
+                    if let Some(splat) = cam.and_then(|c| connect_camera(scene, c, &light_path, s, sampler)) {
+                        splats.push(splat);
+                    }
+                } else if let Some(l) = connect(scene, cam, &light_path, &camera_path, s, t, sampler) {
+                    radiance += l;
+                }
+            }
+        }
+
+        radiance
+    }
+}
+
+// joins the first `s` light vertices to the first `t` camera vertices, for
+// t of at least 2
+fn connect(scene: &Scene, cam: Option<&Camera>, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize,
+           sampler: &mut Sampler) -> Option<Colour> {
+    let pt = &camera_path[t - 1];
+    let lambda = &pt.lambda;
+
+    // the camera subpath left the scene
+    if pt.kind == VertexKind::Background {
+        if s > 0 { return None; }
+        return Some(pt.beta * scene.background(&pt.r_in, lambda) * mis_weight(cam, light_path, camera_path, None, s, t));
+    }
+    let rec = pt.rec.as_ref()?;
+
+    // the camera subpath reached an emitter by itself
+    if s == 0 {
+        let emitted = rec.material.emitted(&pt.r_in, rec, lambda);
+        if emitted.near_zero() { return None; }
+        return Some(pt.beta * emitted * mis_weight(cam, light_path, camera_path, None, s, t));
+    }
+
+    if pt.delta { return None; }
+
+    // a fresh point on a light rather than the light subpath's own start
+    if s == 1 {
+        let (point, pdf_pos) = scene.lights.sample_point(sampler)?;
+        let (l, sampled) = match point {
+            LightPoint::Surface(lrec) => {
+                let dir = lrec.p - pt.p;
+                let f = rec.material.eval(&pt.r_in, rec, &dir, lambda)?;
+                let emitted = emitted_towards(&lrec, &-dir, lambda);
+                let cosine = dot(&unit_vector(dir), &lrec.geo_normal).abs();
+                (pt.beta * f * emitted * (cosine / (dir.norm_sq() * pdf_pos)), Vertex::light(lrec, colour(0, 0, 0), pdf_pos, *lambda))
+            }
+            LightPoint::Delta(light) => {
+                let (dir, li, dist) = light.incident(&pt.p);
+                let f = rec.material.eval(&pt.r_in, rec, &dir, lambda)?;
+                (pt.beta * f * lambda.spectral(li) / pdf_pos, Vertex::delta_light(light, pt.p + dist * dir, colour(0, 0, 0), pdf_pos, *lambda))
+            }
+        };
+        if l.near_zero() || !visible(scene, pt, sampled.p) { return None; }
+
+        return Some(l * mis_weight(cam, light_path, camera_path, Some(&sampled), s, t));
+    }
+
+    let qs = &light_path[s - 1];
+    let qrec = qs.rec.as_ref()?;
+    if qs.delta { return None; }
+
+    let dir = pt.p - qs.p;
+    let fq = qrec.material.eval(&qs.r_in, qrec, &dir, lambda)?;
+    let fp = rec.material.eval(&pt.r_in, rec, &-dir, lambda)?;
+
+    let l = qs.beta * fq * fp * pt.beta * qs.lambda.joined(lambda) / dir.norm_sq();
+    if l.near_zero() || !visible(scene, qs, pt.p) { return None; }
+
+    Some(l * mis_weight(cam, light_path, camera_path, None, s, t))
+}
+
+// light reaching camera vertex `pt` straight from the lights infinitely far
+// away. no other strategy can find them, so it isn't weighted
+fn connect_distant(scene: &Scene, pt: &Vertex) -> Colour {
+    let mut l = colour(0, 0, 0);
+    let Some(rec) = pt.rec.as_ref() else { return l; };
+    if pt.delta { return l; }
+
+    for light in scene.lights.distant() {
+        let (dir, li, _) = light.incident(&pt.p);
+        let Some(f) = rec.material.eval(&pt.r_in, rec, &dir, &pt.lambda) else { continue; };
+        if f.near_zero() || li.near_zero() { continue; }
+        if scene.hit(&rec.spawn_ray(dir), interval(0.001, f64::INFINITY)).is_none() {
+            l += pt.beta * f * pt.lambda.spectral(li);
+        }
+    }
+    l
+}
+
+// light reaching the last of the first `t` camera vertices from the
+// background, along a direction picked from its own distribution
+fn connect_background(scene: &Scene, camera_path: &[Vertex], t: usize, sampler: &mut Sampler) -> Colour {
+    let black = colour(0, 0, 0);
+    let pt = &camera_path[t - 1];
+    let Some(rec) = pt.rec.as_ref() else { return black; };
+    if pt.delta || !scene.background.is_sampled() { return black; }
+
+    let Some((dir, radiance, light_pdf)) = scene.background.sample(sampler) else { return black; };
+    if light_pdf <= 0.0 || radiance.near_zero() { return black; }
+    let Some(f) = rec.material.eval(&pt.r_in, rec, &dir, &pt.lambda) else { return black; };
+    if f.near_zero() { return black; }
+
+    let shadow = rec.spawn_ray(dir);
+    if scene.hit(&shadow, interval(0.001, f64::INFINITY)).is_some() { return black; }
+
+    let sampled = Vertex::background(shadow, colour(0, 0, 0), rec.material.pdf(&pt.r_in, rec, &dir), light_pdf, pt.lambda);
+    pt.beta * f * pt.lambda.spectral(radiance) / light_pdf * mis_weight(None, &[], camera_path, Some(&sampled), 1, t)
+}
+
+// joins the first `s` light vertices straight to a point on the lens, for
+// s of at least 2. the light lands on whichever pixel that line crosses
+fn connect_camera(scene: &Scene, cam: &Camera, light_path: &[Vertex], s: usize, sampler: &mut Sampler) -> Option<Splat> {
+    let qs = &light_path[s - 1];
+    let rec = qs.rec.as_ref()?;
+    if qs.delta { return None; }
+
+    let lens_point = cam.sample_lens(sampler);
+    let (i, j) = cam.raster(lens_point, qs.p)?;
+    let dir = lens_point - qs.p;
+    let f = rec.material.eval(&qs.r_in, rec, &dir, &qs.lambda)?;
+
+    // importance times the lens cosine over the lens density is pdf_dir
+    let l = qs.beta * f * (cam.pdf_dir(lens_point, qs.p) / dir.norm_sq());
+    if l.near_zero() || !visible(scene, qs, lens_point) { return None; }
+
+    let sampled = Vertex::camera(lens_point, qs.lambda);
+    let weight = mis_weight(Some(cam), light_path, &[], Some(&sampled), s, 1);
+    Some(Splat { i, j, colour: l * weight })
+}
+
+// power heuristic over every (s, t) split of the joined path, found by
+// walking out from the join and swapping one forward density for a reverse
+// one at a time. `sampled` stands in for the end vertex a strategy with
+// s == 1 or t == 1 picked afresh
+fn mis_weight(cam: Option<&Camera>, light_path: &[Vertex], camera_path: &[Vertex],
+              sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+    if s + t == 2 { return 1.0; }
+
+    // only the camera subpath escaping (s == 0) and picking a direction
+    // towards it (s == 1) reach the background, both per solid angle at
+    // the last surface
+    let end = if s == 1 { sampled } else if s == 0 { Some(&camera_path[t - 1]) } else { None };
+    if let Some(bg) = end.filter(|v| v.kind == VertexKind::Background) {
+        if s == 1 { return power_heuristic(bg.light_pdf, bg.pdf_fwd); }
+        return if bg.light_pdf > 0.0 { power_heuristic(bg.pdf_fwd, bg.light_pdf) } else { 1.0 };
+    }
+
+    let qs = if s == 1 { sampled } else if s > 1 { Some(&light_path[s - 1]) } else { None };
+    let pt = if t == 1 { sampled.unwrap() } else { &camera_path[t - 1] };
+    let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+    let pt_minus = if t > 1 { Some(&camera_path[t - 2]) } else { None };
+
+    // (pdf_fwd, pdf_rev, delta) as they'd be with this join in place
+    let mut lp: Vec<(f64, f64, bool)> = light_path[..s.saturating_sub(1)].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+    let mut cp: Vec<(f64, f64, bool)> = camera_path[..t.max(1) - 1].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+    cp.push((pt.pdf_fwd, pt.pdf_rev, false));
+    if let Some(q) = qs { lp.push((q.pdf_fwd, q.pdf_rev, false)); }
+
+    cp[t - 1].1 = match qs {
+        Some(q) => q.pdf_to(cam, qs_minus, pt),
+        None => pt.light_pdf
+    };
+    if let Some(pm) = pt_minus {
+        cp[t - 2].1 = match qs {
+            Some(q) => pt.pdf_to(cam, Some(q), pm),
+            None => pt.pdf_emit(pm)
+        };
+    }
+    if let Some(q) = qs { lp[s - 1].1 = pt.pdf_to(cam, pt_minus, q); }
+    if let (Some(q), Some(qm)) = (qs, qs_minus) { lp[s - 2].1 = q.pdf_to(cam, Some(pt), qm); }
+
+    // densities of zero mark delta scattering, which can't be joined anyway
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let mut sum = 0.0;
+
+    let mut ri = 1.0;
+    for i in (1..t).rev() {
+        ri *= remap(cp[i].1) / remap(cp[i].0);
+        // splitting at the lens needs the camera to splat through
+        let possible = i > 1 || cam.is_some();
+        if !cp[i].2 && !cp[i - 1].2 && possible { sum += ri * ri; }
+    }
+
+    // nothing reaches a light with no size by chance, so the light
+    // subpath always has to start there
+    let start = if s == 1 { qs } else { light_path.first() };
+    let delta_light = start.is_some_and(|v| v.light.is_some());
+
+    let mut ri = 1.0;
+    for i in (0..s).rev() {
+        ri *= remap(lp[i].1) / remap(lp[i].0);
+        let delta_before = if i > 0 { lp[i - 1].2 } else { delta_light };
+        if !lp[i].2 && !delta_before { sum += ri * ri; }
+    }
+
+    1.0 / (1.0 + sum)
+}
+
+// starts a subpath at a random point on a light
+fn light_subpath<'a>(scene: &'a Scene, max_vertices: usize, path: &mut Vec<Vertex<'a>>, lambda: Wavelengths,
+                     sampler: &mut Sampler) {
+    if max_vertices == 0 { return; }
+    let Some(e) = scene.lights.sample_emission(None, &lambda, sampler) else { return; };
+
+    let beta = e.power();
+    let start = match (e.rec, e.light) {
+        (Some(rec), _) => Vertex::light(rec, e.emitted / e.pdf_pos, e.pdf_pos, lambda),
+        (None, Some(light)) => Vertex::delta_light(light, e.ray.ori(), e.emitted / e.pdf_pos, e.pdf_pos, lambda),
+        (None, None) => return
+    };
+    path.push(start);
+    random_walk(scene, e.ray, beta, e.pdf_dir, max_vertices, path, sampler);
+}
+
+// extends `path` by following material scattering along `r`, which the
+// last vertex picked with solid angle density `pdf_dir`. returns the ray,
+// throughput and that density if the path leaves the scene
+fn random_walk(scene: &Scene, mut r: Ray, mut beta: Colour, mut pdf_dir: f64, max_vertices: usize,
+               path: &mut Vec<Vertex>, sampler: &mut Sampler) -> Option<(Ray, Colour, f64)> {
+    let mut lambda = path.last().unwrap().lambda;
+    while path.len() < max_vertices {
+        let Some(rec) = scene.hit(&r, interval(0.001, f64::INFINITY)) else { return Some((r, beta, pdf_dir)); };
+        let light_pdf = if rec.material.is_emissive() { scene.lights.pdf_point(&r) } else { 0.0 };
+
+        let mut v = Vertex::surface(rec, r, beta, light_pdf, lambda);
+        v.pdf_fwd = convert_density(pdf_dir, path.last().unwrap(), &v);
+        path.push(v);
+
+        let n = path.len();
+        let rec = path[n - 1].rec.as_ref().unwrap();
+        let Some((scatrd_ray, attenuation)) = rec.material.scatter(&r, rec, &mut lambda, sampler) else { break; };
+        let dir = scatrd_ray.dir();
+
+        let delta = rec.material.eval(&r, rec, &dir, &lambda).is_none();
+        let (pdf_fwd, pdf_rev) = if delta { (0.0, 0.0) } else {
+            // the reverse density treats the new direction as the incoming one
+            let back = ray(rec.p + dir, -dir);
+            (rec.material.pdf(&r, rec, &dir), rec.material.pdf(&back, rec, &-r.dir()))
+        };
+
+        path[n - 1].delta = delta;
+        let pdf_rev = convert_density(pdf_rev, &path[n - 1], &path[n - 2]);
+        path[n - 2].pdf_rev = pdf_rev;
+
+        beta = beta * attenuation;
+        pdf_dir = pdf_fwd;
+        r = scatrd_ray;
+        if beta.near_zero() { break; }
+    }
+
+    None
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(p: Point3, lambda: Wavelengths) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p,
+            rec: None,
+            light: None,
+            r_in: ray(p, vec3(0, 0, 0)),
+            beta: colour(1, 1, 1),
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+            light_pdf: 0.0,
+            lambda
+        }
+    }
+
+    fn light(rec: HitRecord, beta: Colour, pdf_pos: f64, lambda: Wavelengths) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p: rec.p,
+            r_in: ray(rec.p, vec3(0, 0, 0)),
+            rec: Some(rec),
+            light: None,
+            beta,
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+            light_pdf: pdf_pos,
+            lambda
+        }
+    }
+
+    // at `p`, a point or spot light. `pdf_pos` is the chance of picking it
+    fn delta_light(light: &'a Light, p: Point3, beta: Colour, pdf_pos: f64, lambda: Wavelengths) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p,
+            rec: None,
+            light: Some(light),
+            r_in: ray(p, vec3(0, 0, 0)),
+            beta,
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+            light_pdf: pdf_pos,
+            lambda
+        }
+    }
+
+    // a step along `r` from where it left, standing in for a point
+    // infinitely far away
+    fn background(r: Ray, beta: Colour, pdf_fwd: f64, light_pdf: f64, lambda: Wavelengths) -> Self {
+        Self {
+            kind: VertexKind::Background,
+            p: r.at(1.0),
+            rec: None,
+            light: None,
+            r_in: r,
+            beta,
+            delta: false,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            light_pdf,
+            lambda
+        }
+    }
+
+    fn surface(rec: HitRecord, r_in: Ray, beta: Colour, light_pdf: f64, lambda: Wavelengths) -> Self {
+        Self {
+            kind: VertexKind::Surface,
+            p: rec.p,
+            rec: Some(rec),
+            light: None,
+            r_in,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            light_pdf,
+            lambda
+        }
+    }
+
+    // area density at `next` of scattering towards it, having come from `prev`
+    fn pdf_to(&self, cam: Option<&Camera>, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let pdf = match self.kind {
+            VertexKind::Camera => cam.map_or(0.0, |c| c.pdf_dir(self.p, next.p)),
+            VertexKind::Light => return self.pdf_emit(next),
+            VertexKind::Background => return 0.0,
+            VertexKind::Surface => {
+                let (Some(rec), Some(prev)) = (&self.rec, prev) else { return 0.0; };
+                rec.material.pdf(&ray(prev.p, self.p - prev.p), rec, &(next.p - self.p))
+            }
+        };
+        convert_density(pdf, self, next)
+    }
+
+    // area density at `next` of a light subpath starting here heading to it
+    fn pdf_emit(&self, next: &Vertex) -> f64 {
+        let dir = unit_vector(next.p - self.p);
+        let pdf = match (&self.rec, self.light) {
+            (Some(rec), _) => emission_pdf(rec, &dir),
+            (None, Some(light)) => light.pdf_emit(&dir),
+            (None, None) => return 0.0
+        };
+        convert_density(pdf, self, next)
+    }
+}
+
+// solid angle density at `from` to area density at `to`
+fn convert_density(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+    let dir = to.p - from.p;
+    let dist_sq = dir.norm_sq();
+    if dist_sq == 0.0 { return 0.0; }
+
+    match &to.rec {
+        Some(rec) => pdf * dot(&rec.geo_normal, &dir).abs() / (dist_sq * dist_sq.sqrt()),
+        None => pdf / dist_sq
+    }
+}
+
+fn visible(scene: &Scene, from: &Vertex, to: Point3) -> bool {
+    let origin = match &from.rec {
+        Some(rec) => rec.spawn_ray(to - from.p).ori(),
+        None => from.p
+    };
+    let dir = to - origin;
+    let dist = dir.norm();
+    scene.hit(&ray(origin, dir / dist), interval(0.001, dist * (1.0 - 1.0e-6))).is_none()
+}
@@ -0,0 +1,284 @@
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use std::sync::Arc;
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 1.0; // relative to one primitive intersection
+const MAX_DEPTH: usize = 60; // keeps the traversal stack fixed-size
+
+struct BvhNode {
+    bbox: Aabb,
+    offset: usize, // first primitive for leaves, second child for interior nodes
+    count: usize // 0 for interior nodes
+}
+
+// flattened tree over primitive indices, shared by every hittable that needs one
+pub struct BvhTree {
+    nodes: Vec<BvhNode>
+}
+
+pub struct Bvh {
+    tree: BvhTree,
+    objects: Vec<Arc<Hittable>>
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bbox: Aabb,
+    count: usize
+}
+
+impl BvhTree {
+    // builds a tree over `bounds` using the surface area heuristic.
+    // returns the tree and the primitive order its leaves refer to
+    pub fn new(bounds: &[Aabb]) -> (Self, Vec<usize>) {
+        let mut prims: Vec<(usize, Aabb)> = bounds.iter().copied().enumerate().collect();
+        let mut tree = Self { nodes: Vec::with_capacity(2 * bounds.len().max(1)) };
+
+        if prims.is_empty() {
+            tree.nodes.push(BvhNode { bbox: Aabb::empty(), offset: 0, count: 0 });
+            return (tree, Vec::new());
+        }
+
+        tree.build(&mut prims, 0, 0);
+        (tree, prims.into_iter().map(|(i, _)| i).collect())
+    }
+
+    pub fn bbox(&self) -> Aabb {
+        self.nodes[0].bbox
+    }
+
+    fn build(&mut self, prims: &mut [(usize, Aabb)], first: usize, depth: usize) -> usize {
+        let bbox = prims.iter().fold(Aabb::empty(), |b, (_, p)| Aabb::surrounding(&b, p));
+        let centroid_box = prims.iter().fold(Aabb::empty(), |b, (_, p)| {
+            let c = p.centroid();
+            Aabb::surrounding(&b, &Aabb::from_points(c, c))
+        });
+
+        let node_idx = self.nodes.len();
+        self.nodes.push(BvhNode { bbox, offset: first, count: prims.len() });
+
+        if prims.len() <= 1 || depth >= MAX_DEPTH { return node_idx; }
+
+        // find the cheapest split plane over all three axes
+        let mut best: Option<(f64, usize, usize)> = None; // (cost, axis, bin)
+        for axis in 0..3 {
+            let extent = centroid_box.axis(axis);
+            if extent.size() <= 0.0 { continue; }
+
+            let mut bins = [Bin { bbox: Aabb::empty(), count: 0 }; SAH_BINS];
+            for (_, p) in prims.iter() {
+                let b = bin_index(p.centroid()[axis], extent);
+                bins[b].count += 1;
+                bins[b].bbox = Aabb::surrounding(&bins[b].bbox, p);
+            }
+
+            // sweep from the right to get the cost of everything past each plane
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0; SAH_BINS];
+            let mut acc = Bin { bbox: Aabb::empty(), count: 0 };
+            for b in (1..SAH_BINS).rev() {
+                acc.bbox = Aabb::surrounding(&acc.bbox, &bins[b].bbox);
+                acc.count += bins[b].count;
+                right_area[b] = acc.bbox.surface_area();
+                right_count[b] = acc.count;
+            }
+
+            let mut acc = Bin { bbox: Aabb::empty(), count: 0 };
+            for b in 1..SAH_BINS {
+                acc.bbox = Aabb::surrounding(&acc.bbox, &bins[b - 1].bbox);
+                acc.count += bins[b - 1].count;
+                if acc.count == 0 || right_count[b] == 0 { continue; }
+
+                let cost = acc.count as f64 * acc.bbox.surface_area() + right_count[b] as f64 * right_area[b];
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        let area = bbox.surface_area();
+        let leaf_cost = prims.len() as f64;
+        let mid = match best {
+            Some((cost, axis, b)) => {
+                let split_cost = if area > 0.0 { TRAVERSAL_COST + cost / area } else { TRAVERSAL_COST + leaf_cost };
+                if split_cost >= leaf_cost && prims.len() <= MAX_LEAF_SIZE {
+                    return node_idx;
+                }
+                let extent = *centroid_box.axis(axis);
+                partition(prims, |(_, p)| bin_index(p.centroid()[axis], &extent) < b)
+            }
+            None => {
+                // every centroid is in the same place, so just halve the list
+                if prims.len() <= MAX_LEAF_SIZE { return node_idx; }
+                prims.len() / 2
+            }
+        };
+
+        let (left, right) = prims.split_at_mut(mid);
+        self.build(left, first, depth + 1);
+        let right_idx = self.build(right, first + mid, depth + 1);
+
+        let node = &mut self.nodes[node_idx];
+        node.offset = right_idx;
+        node.count = 0;
+        node_idx
+    }
+
+    // `hit_prim` is called with the leaf-order index of each candidate and the
+    // interval still worth searching; the closest record is returned
+    pub fn hit<F>(&self, r: &Ray, ray_t: Interval, mut hit_prim: F) -> Option<HitRecord>
+    where F: FnMut(usize, Interval) -> Option<HitRecord> {
+        let mut closest_so_far = ray_t.max();
+        let mut temprec: Option<HitRecord> = None;
+
+        if self.nodes[0].bbox.is_empty() { return None; }
+        let root_t = self.nodes[0].bbox.hit(r, ray_t)?;
+
+        // (node, distance at which the ray enters its box)
+        let mut stack = [(0usize, 0.0); MAX_DEPTH + 2];
+        stack[0] = (0, root_t.min());
+        let mut sp = 1;
+
+        while sp > 0 {
+            sp -= 1;
+            let (idx, t_enter) = stack[sp];
+            if t_enter > closest_so_far { continue; } // something closer was found since this was pushed
+            let node = &self.nodes[idx];
+
+            if node.count > 0 {
+                for i in node.offset..node.offset + node.count {
+                    if let Some(rec) = hit_prim(i, interval(ray_t.min(), closest_so_far)) {
+                        closest_so_far = rec.t;
+                        temprec = Some(rec);
+                    }
+                }
+                continue;
+            }
+
+            let search = interval(ray_t.min(), closest_so_far);
+            let (left, right) = (idx + 1, node.offset);
+            let hit_l = self.nodes[left].bbox.hit(r, search);
+            let hit_r = self.nodes[right].bbox.hit(r, search);
+
+            // push the far child first so the near one is searched first
+            match (hit_l, hit_r) {
+                (Some(tl), Some(tr)) => {
+                    let (near, far) = if tl.min() <= tr.min() { ((left, tl), (right, tr)) } else { ((right, tr), (left, tl)) };
+                    stack[sp] = (far.0, far.1.min());
+                    stack[sp + 1] = (near.0, near.1.min());
+                    sp += 2;
+                }
+                (Some(tl), None) => { stack[sp] = (left, tl.min()); sp += 1; }
+                (None, Some(tr)) => { stack[sp] = (right, tr.min()); sp += 1; }
+                (None, None) => {}
+            }
+        }
+
+        temprec
+    }
+}
+
+impl Bvh {
+    pub fn new(list: HittableList) -> Self {
+        let objects = list.into_objects();
+        let bounds: Vec<Aabb> = objects.iter().map(|o| o.aabb()).collect();
+        let (tree, order) = BvhTree::new(&bounds);
+
+        Self { tree, objects: order.into_iter().map(|i| objects[i].clone()).collect() }
+    }
+}
+
+impl Hit for Bvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.tree.hit(r, ray_t, |i, t| self.objects[i].hit(r, t))
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.tree.bbox()
+    }
+
+    fn collect_emitters(&self, out: &mut Vec<Arc<Hittable>>) {
+        collect_emitters(&self.objects, out);
+    }
+}
+
+#[inline(always)]
+fn bin_index(c: f64, extent: &Interval) -> usize {
+    let b = (SAH_BINS as f64 * (c - extent.min()) / extent.size()) as usize;
+    b.min(SAH_BINS - 1)
+}
+
+// in-place partition, returns the number of elements satisfying `pred`
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], pred: F) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::*;
+    use crate::material::*;
+    use crate::sampler::*;
+    use crate::texture::*;
+
+    // spheres and triangles scattered through a box, some of them flat
+    // along an axis, built into a bvh and a plain list of the same objects
+    fn scene(sampler: &mut Sampler) -> (Bvh, HittableList) {
+        let material = Arc::new(Material::Lambertian(Lambertian { albedo: solid(colour(0.5, 0.5, 0.5)) }));
+        let mut point = || point3(10.0 * sampler.next_1d() - 5.0, 10.0 * sampler.next_1d() - 5.0, 10.0 * sampler.next_1d() - 5.0);
+        let mut objects = Vec::new();
+        for i in 0..300 {
+            let (a, b, c) = (point(), point(), point());
+            objects.push(Hittable::Sphere(Sphere::new(a, 0.05 + 0.01 * (i % 20) as f64, material.clone())));
+            let (b, c) = (a + 0.2 * (b - a), a + 0.2 * (c - a));
+            let (b, c) = if i % 3 == 0 { (point3(b.x(), b.y(), a.z()), point3(c.x(), c.y(), a.z())) } else { (b, c) };
+            objects.push(Hittable::Triangle(Triangle::new(a, b, c, material.clone())));
+        }
+
+        let (mut bvh, mut list) = (HittableList::new(), HittableList::new());
+        for o in objects {
+            let o = Arc::new(o);
+            bvh.add(o.clone());
+            list.add(o);
+        }
+        (Bvh::new(bvh), list)
+    }
+
+    #[test]
+    fn hits_the_same_as_brute_force() {
+        let mut sampler = Sampler::Independent(IndependentSampler::seeded(11));
+        let (bvh, list) = scene(&mut sampler);
+
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let origin = point3(14.0 * sampler.next_1d() - 7.0, 14.0 * sampler.next_1d() - 7.0, 14.0 * sampler.next_1d() - 7.0);
+            let r = ray(origin, randvec_in_unit_sphere(&mut sampler));
+            let t_max = if sampler.next_1d() < 0.5 { f64::INFINITY } else { 6.0 };
+
+            match (bvh.hit(&r, interval(0.001, t_max)), list.hit(&r, interval(0.001, t_max))) {
+                (Some(a), Some(b)) => {
+                    assert!((a.t - b.t).abs() < 1.0e-9 && (a.p - b.p).norm() < 1.0e-9, "bvh hit at {} but the list at {}", a.t, b.t);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (a, b) => panic!("bvh hit {} but the list {}", a.is_some(), b.is_some())
+            }
+        }
+        assert!(hits > 500, "only {hits} rays hit anything");
+
+        let (bb, lb) = (bvh.aabb(), list.aabb());
+        for n in 0..3 {
+            assert!(bb.axis(n).min() <= lb.axis(n).min() + 1.0e-9 && bb.axis(n).max() >= lb.axis(n).max() - 1.0e-9);
+        }
+    }
+}
@@ -1,19 +1,16 @@
 use crate::vec3::*;
 use crate::ray::*;
 use crate::util::*;
-use crate::hittable::*;
-use crate::material::*;
-use std::f64::INFINITY;
+use crate::scene::*;
+use crate::integrator::*;
 use std::fs::File;
 use std::io::stdout;
 use std::io::BufWriter;
 use std::io::Write;
 use std::time::Instant;
-use rand::thread_rng;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use crate::sampler::*;
+use crate::spectrum::*;
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
 
 pub struct Camera {
     aspect_ratio: f64,
@@ -23,7 +20,6 @@ pub struct Camera {
     pixel00: Point3,
     delta_u: Point3,
     delta_v: Point3,
-    pub max_ray_bounces: i32,
     pub sample_rate: i32,
     pub vfov: f64,
     pub look_from: Point3,
@@ -31,10 +27,19 @@ pub struct Camera {
     pub vup: Vec3,
     pub defocus_angle: f64,
     pub focus_distance: f64,
+    pub spectral: bool, // trace wavelengths rather than rgb, not supported by sppm
     defocus_disc_u: Vec3,
     defocus_disc_v: Vec3
 }
 
+// light that reached the film through some pixel other than the one being
+// sampled, e.g. from a path traced out of a light
+pub struct Splat {
+    pub i: i32,
+    pub j: i32,
+    pub colour: Colour
+}
+
 impl Camera {
     pub fn new(ratio_: f64, width: i32) -> Self {
         let aspect_ratio = ratio_;
@@ -69,21 +74,21 @@ impl Camera {
         let pixel00 = viewport_upperleft + 0.5 * (delta_u + delta_v);
 
         Self {
-            aspect_ratio: aspect_ratio,
-            image_width: image_width,
-            image_height: image_height,
+            aspect_ratio,
+            image_width,
+            image_height,
             center: camera_center,
-            pixel00: pixel00,
-            delta_u: delta_u,
-            delta_v: delta_v,
-            max_ray_bounces: 50,
+            pixel00,
+            delta_u,
+            delta_v,
             sample_rate: 40,
             vfov: 90.0,
-            look_from: look_from,
-            look_at: look_at,
-            vup: vup,
+            look_from,
+            look_at,
+            vup,
             defocus_angle: 0.0,
             focus_distance: 3.4,
+            spectral: false,
             defocus_disc_u: vec3(0,0,0),
             defocus_disc_v: vec3(0,0,0)
         }
@@ -125,101 +130,184 @@ impl Camera {
         let defocus_disc_v = v * defocus_radius;
 
         Self {
-            aspect_ratio: aspect_ratio,
-            image_width: image_width,
-            image_height: image_height,
+            aspect_ratio,
+            image_width,
+            image_height,
             center: camera_center,
-            pixel00: pixel00,
-            delta_u: delta_u,
-            delta_v: delta_v,
-            max_ray_bounces: self.max_ray_bounces,
+            pixel00,
+            delta_u,
+            delta_v,
             sample_rate: self.sample_rate,
             vfov: self.vfov,
-            look_from: look_from,
-            look_at: look_at,
-            vup: vup,
+            look_from,
+            look_at,
+            vup,
             defocus_angle: self.defocus_angle,
             focus_distance: self.focus_distance,
-            defocus_disc_u: defocus_disc_u,
-            defocus_disc_v: defocus_disc_v
+            spectral: self.spectral,
+            defocus_disc_u,
+            defocus_disc_v
         }
     }
 
-    fn ray_clr(r: &Ray, world: &Hittable, max_bounces: i32) -> Colour {
-        if max_bounces <= 0 { return colour(0, 0, 0) /*black*/ } // stop calculating rays at max depth reached
-        if let Some(rec) = world.hit(r, interval(0.001, INFINITY)) {
-            let scattering = rec.material.scatter(r, &rec);
-            if scattering.is_some() {
-                let (scatrd_ray, attenuation) = scattering.unwrap();
-                return attenuation * Self::ray_clr(&scatrd_ray, world, max_bounces - 1);
-            }
-            return colour(0, 0, 0);
-        }
-    
-        let uni_dir = unit_vector(r.dir());
-        let a = 0.5 * (uni_dir.y() + 1.0);
-    
-        (1.0 - a) * colour(1, 1, 1) + a * colour(0.5, 0.7, 1.0)
-    }
-    
-    pub fn render(&self, world: &Hittable) {
+    pub fn render(&self, scene: &Scene, integrator: &Integrator) {
         let now = Instant::now();
-        let mut imfile = File::create("image.ppm").unwrap();
-
-        writeln!(&mut imfile, "P3");
-        writeln!(&mut imfile, "{} {}", self.image_width, self.image_height);
-        writeln!(&mut imfile, "255");
-    
-        let mut stdout = stdout();
         println!("\n\nBeginning render...");
 
-        let write_file = Arc::new(Mutex::new(BufWriter::new(imfile)));
+        let film = match integrator.render(self, scene) {
+            Some(film) => film,
+            None => self.sample_pixels(scene, integrator)
+        };
+        self.write_image(&film);
+
+        let elapsed = now.elapsed().as_secs();
+        println!("\nFinished render! Took {}min {}sec.", elapsed / 60, elapsed % 60)
+    }
+
+    // averages sample_rate camera samples per pixel, plus whatever the
+    // integrator splats
+    fn sample_pixels(&self, scene: &Scene, integrator: &Integrator) -> Vec<Colour> {
+        let mut film = vec![colour(0, 0, 0); (self.image_width * self.image_height) as usize];
+        let mut splat_film = film.clone();
 
         for j in 0..self.image_height {
-            let frac_done = ((j as f64 / self.image_height as f64) * 40.0) as usize;
-            print!("\r[{char:=>width$}{dhar: >left$}] | lines left: {ll:0>3} ",
-                     ll=self.image_height-j-1, char=">", 
-                     width=frac_done, dhar="", left=39-frac_done
-            );
-            let _ = stdout.flush();
+            print_progress(j, self.image_height, "lines");
 
             for i in 0..self.image_width {
-                let pixclr = (0..self.sample_rate).into_par_iter().map(|_s| -> Vec3 { // 100 samples per pixel
-                    let mut rng = thread_rng();
-                    let r = self.get_ray(i, j, &mut rng);
-                    Self::ray_clr(&r, world, self.max_ray_bounces)
-                }).sum::<Vec3>();
-
-                let mut out_file = write_file.lock().unwrap();
-                write_colour((1.0/self.sample_rate as f64)*pixclr, &mut out_file);
+                let (pixclr, splats) = (0..self.sample_rate).into_par_iter().map(|_s| { // 100 samples per pixel
+                    let mut sampler = Sampler::Independent(IndependentSampler::new());
+                    let mut lambda = self.wavelengths(&mut sampler);
+                    let mut splats = Vec::new();
+                    let r = self.get_ray(i, j, &mut sampler);
+                    let pixclr = integrator.sample(&r, scene, self, &mut lambda, &mut splats, &mut sampler);
+
+                    // splats are in the sample's wavelengths too
+                    for s in &mut splats {
+                        s.colour = lambda.to_rgb(s.colour);
+                    }
+                    (lambda.to_rgb(pixclr), splats)
+                }).reduce(|| (colour(0, 0, 0), Vec::new()), |(a, mut sa), (b, sb)| {
+                    sa.extend(sb);
+                    (a + b, sa)
+                });
+
+                film[(j * self.image_width + i) as usize] = (1.0/self.sample_rate as f64)*pixclr;
+                for s in splats {
+                    splat_film[(s.j * self.image_width + s.i) as usize] += s.colour;
+                }
             }
         }
 
-        write_file.lock().unwrap().flush().unwrap();
+        // every camera sample may splat anywhere, so each pixel's share is
+        // over the samples per pixel rather than the total
+        for (px, splat) in film.iter_mut().zip(splat_film) {
+            *px += (1.0/self.sample_rate as f64)*splat;
+        }
+        film
+    }
+
+    // the wavelengths a new camera sample is traced at, which the film
+    // turns back into rgb with to_rgb
+    pub fn wavelengths(&self, sampler: &mut Sampler) -> Wavelengths {
+        let u = sampler.next_1d();
+        if self.spectral { Wavelengths::sample(u) } else { Wavelengths::rgb(u) }
+    }
 
-        let elapsed = now.elapsed().as_secs();
-        println!("\nFinished render! Took {}min {}sec.", elapsed / 60, elapsed % 60)
+    // writes one colour per pixel, row by row, to image.ppm
+    pub fn write_image(&self, film: &[Colour]) {
+        let mut imfile = File::create("image.ppm").unwrap();
+
+        writeln!(&mut imfile, "P3").unwrap();
+        writeln!(&mut imfile, "{} {}", self.image_width, self.image_height).unwrap();
+        writeln!(&mut imfile, "255").unwrap();
+
+        let mut out_file = BufWriter::new(imfile);
+        for pixel in film {
+            write_colour(*pixel, &mut out_file);
+        }
+        out_file.flush().unwrap();
+    }
+
+    // a point on the lens, spread the way get_ray spreads ray origins
+    pub fn sample_lens(&self, sampler: &mut Sampler) -> Point3 {
+        if self.defocus_angle <= 0.0 { self.center } else { self.defocus_disc_sample(sampler) }
+    }
+
+    // pixel the line from `lens_point` through `p` lands in, if any
+    pub fn raster(&self, lens_point: Point3, p: Point3) -> Option<(i32, i32)> {
+        let forward = self.forward();
+        let dir = p - lens_point;
+        let cosine = dot(&dir, &forward);
+        if cosine <= 0.0 { return None; }
+
+        // where it crosses the plane the pixels sit on
+        let q = lens_point + (dot(&(self.pixel00 - lens_point), &forward) / cosine) * dir;
+        let rel = q - self.pixel00;
+        let x = dot(&rel, &self.delta_u) / self.delta_u.norm_sq() + 0.5;
+        let y = dot(&rel, &self.delta_v) / self.delta_v.norm_sq() + 0.5;
+        let (i, j) = (x.floor() as i32, y.floor() as i32);
+
+        let inside = x >= 0.0 && y >= 0.0 && i < self.image_width && j < self.image_height;
+        if inside { Some((i, j)) } else { None }
+    }
+
+    // solid angle density with which rays from `lens_point` towards `p` are
+    // picked, counting samples over the whole film. importance is
+    // normalised so that it and the lens density cancel down to this too
+    pub fn pdf_dir(&self, lens_point: Point3, p: Point3) -> f64 {
+        if self.raster(lens_point, p).is_none() { return 0.0; }
+
+        let forward = self.forward();
+        let cosine = dot(&unit_vector(p - lens_point), &forward);
+        let dist = dot(&(self.pixel00 - self.center), &forward);
+        let film_area = self.delta_u.norm() * self.delta_v.norm() * (self.image_width * self.image_height) as f64;
+
+        dist * dist / (film_area * cosine * cosine * cosine)
+    }
+
+    #[inline(always)]
+    fn forward(&self) -> Vec3 {
+        unit_vector(cross(&self.delta_u, &self.delta_v))
+    }
+
+    pub fn image_width(&self) -> i32 {
+        self.image_width
+    }
+
+    pub fn image_height(&self) -> i32 {
+        self.image_height
+    }
+
+    // a ray through a random spot in pixel (i, j)
+    pub fn get_ray(&self, i: i32, j: i32, sampler: &mut Sampler) -> Ray {
+        let (dx, dy) = sampler.next_2d();
+        self.get_ray_at(i as f64 + dx, j as f64 + dy, sampler)
     }
 
-    fn get_ray(&self, i: i32, j: i32, rng: &mut ThreadRng) -> Ray {
-        let offset = sample_square(rng);
+    // a ray through raster position (x, y), where pixel (i, j) covers
+    // [i, i + 1) x [j, j + 1)
+    pub fn get_ray_at(&self, x: f64, y: f64, sampler: &mut Sampler) -> Ray {
         let pixel_sample = self.pixel00
-                            + (i as f64 + offset.x())*self.delta_u
-                            + (j as f64 + offset.y())*self.delta_v;
+                            + (x - 0.5)*self.delta_u
+                            + (y - 0.5)*self.delta_v;
         
-        let ray_origin = if self.defocus_angle <= 0.0 { self.center } else { self.defocus_disc_sample() };
+        let ray_origin = self.sample_lens(sampler);
 
         ray(ray_origin, pixel_sample - ray_origin)
     }
 
-    fn defocus_disc_sample(&self) -> Vec3 {
-        let p = randvec_in_unit_disc();
+    fn defocus_disc_sample(&self, sampler: &mut Sampler) -> Vec3 {
+        let p = randvec_in_unit_disc(sampler);
         self.center + (p.x() * self.defocus_disc_u) + (p.y() * self.defocus_disc_v)
     }
 }
 
-fn sample_square(rng: &mut ThreadRng) -> Vec3 {
-    let rand1: f64 = rng.gen();
-    let rand2: f64 = rng.gen();
-    vec3(rand1 - 0.5, rand2 - 0.5, 0)
+// progress bar for `done` of `total` steps, `what` names the steps
+pub fn print_progress(done: i32, total: i32, what: &str) {
+    let frac_done = ((done as f64 / total as f64) * 40.0) as usize;
+    print!("\r[{char:=>width$}{dhar: >left$}] | {what} left: {ll:0>3} ",
+             ll=total-done-1, char=">",
+             width=frac_done, dhar="", left=39-frac_done
+    );
+    let _ = stdout().flush();
 }
\ No newline at end of file
@@ -2,17 +2,25 @@ use crate::vec3::*;
 use crate::ray::*;
 use crate::util::*;
 use crate::material::*;
-use std::f64::EPSILON;
-use std::rc::Rc;
+use crate::bvh::*;
+use crate::mesh::*;
+use crate::medium::*;
+use std::f64::consts::PI;
 use std::sync::Arc;
 use std::vec::Vec;
 use enum_dispatch::enum_dispatch;
+use crate::sampler::*;
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
-    pub normal: Vec3,
+    pub normal: Vec3, // shading normal, used by materials
+    pub geo_normal: Vec3, // true surface normal, for offsetting and side tests
     pub t: f64,
+    pub u: f64, // surface coordinates for textures
+    pub v: f64,
+    pub dpdu: Vec3, // surface tangents along u and v
+    pub dpdv: Vec3,
     pub front_face: bool,
     pub material: Arc<Material>
 }
@@ -20,30 +28,67 @@ pub struct HitRecord {
 pub struct Sphere {
     center: Point3,
     radius: f64,
-    material: Arc<Material>
+    material: Arc<Material>,
+    bbox: Aabb
 }
 
 pub struct Triangle {
     v1: Point3,
     v2: Point3,
     v3: Point3,
-    material: Arc<Material>
+    uvs: [(f64, f64); 3],
+    material: Arc<Material>,
+    bbox: Aabb
 }
 
 pub struct HittableList {
-    objects: Vec<Arc<Hittable>>
+    objects: Vec<Arc<Hittable>>,
+    bbox: Aabb
 }
 
 #[enum_dispatch]
 pub trait Hit {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
+    fn aabb(&self) -> Aabb;
+
+    // solid angle density with which `random` picks `dir` from `origin`
+    fn pdf_value(&self, _origin: &Point3, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    // direction from `origin` to a random point on the surface
+    fn random(&self, _origin: &Point3, _sampler: &mut Sampler) -> Vec3 {
+        vec3(1, 0, 0)
+    }
+
+    // a point spread uniformly over the surface, as seen from outside, for
+    // starting paths at emitters. None for shapes that can't be sampled
+    fn sample_surface(&self, _sampler: &mut Sampler) -> Option<HitRecord> {
+        None
+    }
+
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    // true for primitives whose material emits light
+    fn is_emitter(&self) -> bool {
+        false
+    }
+
+    // gathers the emitting primitives held below this one
+    fn collect_emitters(&self, _out: &mut Vec<Arc<Hittable>>) {}
 }
 
 #[enum_dispatch(Hit)]
 pub enum Hittable {
     Sphere(Sphere),
     HittableList(HittableList),
-    Triangle(Triangle)
+    Triangle(Triangle),
+    Bvh(Bvh),
+    Mesh(Mesh),
+    ConstantMedium(ConstantMedium),
+    GridVolume(GridVolume)
 }
 
 impl Hit for Sphere {
@@ -67,11 +112,62 @@ impl Hit for Sphere {
             let outward_normal = (r.at(root) - self.center) / self.radius;
             let mut rec = HitRecord::new(r, outward_normal, r.at(root), root, self.material.clone()); // performance?
             rec.set_outward_normal();
+            let (u, v, dpdu, dpdv) = self.surface_coords(&outward_normal);
+            rec.set_surface_coords(u, v, dpdu, dpdv);
 
-            return Some(rec);
+            Some(rec)
         } else {
-            return None;
+            None
+        }
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        let Some(rec) = self.hit(&ray(*origin, *dir), interval(0.001, f64::INFINITY)) else { return 0.0; };
+
+        let dist_sq = (self.center - *origin).norm_sq();
+        let r_sq = self.radius * self.radius;
+        if dist_sq > r_sq {
+            // uniform over the cone of directions the sphere covers
+            let cos_theta_max = (1.0 - r_sq / dist_sq).sqrt();
+            1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+        } else {
+            // uniform over the area, seen from inside
+            let cosine = dot(&unit_vector(*dir), &rec.geo_normal).abs();
+            let dist = rec.t * dir.norm();
+            dist * dist / (cosine * 4.0 * PI * r_sq)
+        }
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut Sampler) -> Vec3 {
+        let dir = self.center - *origin;
+        let dist_sq = dir.norm_sq();
+        let r_sq = self.radius * self.radius;
+        if dist_sq <= r_sq {
+            return self.center + self.radius * randvec_in_unit_sphere(sampler) - *origin;
         }
+
+        let cos_theta_max = (1.0 - r_sq / dist_sq).sqrt();
+        randvec_in_cone(&unit_vector(dir), cos_theta_max, sampler)
+    }
+
+    fn sample_surface(&self, sampler: &mut Sampler) -> Option<HitRecord> {
+        let n = randvec_in_unit_sphere(sampler);
+        let mut rec = surface_record(self.center + self.radius * n, n, self.material.clone());
+        let (u, v, dpdu, dpdv) = self.surface_coords(&n);
+        rec.set_surface_coords(u, v, dpdu, dpdv);
+        Some(rec)
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
     }
 }
 
@@ -91,52 +187,154 @@ impl Hit for HittableList {
 
         temprec
     }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn collect_emitters(&self, out: &mut Vec<Arc<Hittable>>) {
+        collect_emitters(&self.objects, out);
+    }
 }
-// TODO: fix rendering for Metal material
-// this code still doesn't work properly
-// maybe normal vector direction isn't right? <----- yes, it isn't, why?
+
 impl Hit for Triangle {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        // moeller-trumbore
-        let e1 = self.v2 - self.v1; // edge_1
-        let e2 = self.v3 - self.v1;
-        let r_x_e2 = cross(&r.dir(), &e2);
-        let dot_prod = dot(&e1, &r_x_e2); // ray.dir() dot normal vec
-        
-        if dot_prod > -EPSILON && dot_prod < EPSILON {
-            return None; // ray is parallel, no intersection
+        let (t, u, v) = intersect_triangle(r, ray_t, self.v1, self.v2, self.v3)?;
+        let mut rec = triangle_record(r, t, self.v1, self.v2, self.v3, self.material.clone());
+        let (su, sv, dpdu, dpdv) = triangle_surface_coords([self.v1, self.v2, self.v3], self.uvs, u, v);
+        rec.set_surface_coords(su, sv, dpdu, dpdv);
+        Some(rec)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vec3) -> f64 {
+        let Some(rec) = self.hit(&ray(*origin, *dir), interval(0.001, f64::INFINITY)) else { return 0.0; };
+
+        // uniform over the area, converted to solid angle
+        let cosine = dot(&unit_vector(*dir), &rec.geo_normal).abs();
+        let dist = rec.t * dir.norm();
+        dist * dist / (cosine * self.area())
+    }
+
+    fn random(&self, origin: &Point3, sampler: &mut Sampler) -> Vec3 {
+        let (b2, b3) = uniform_barycentrics(sampler);
+        (1.0 - b2 - b3) * self.v1 + b2 * self.v2 + b3 * self.v3 - *origin
+    }
+
+    fn sample_surface(&self, sampler: &mut Sampler) -> Option<HitRecord> {
+        let (b2, b3) = uniform_barycentrics(sampler);
+        let p = (1.0 - b2 - b3) * self.v1 + b2 * self.v2 + b3 * self.v3;
+        let n = unit_vector(cross(&(self.v2 - self.v1), &(self.v3 - self.v1)));
+        let mut rec = surface_record(p, n, self.material.clone());
+        let (u, v, dpdu, dpdv) = triangle_surface_coords([self.v1, self.v2, self.v3], self.uvs, b2, b3);
+        rec.set_surface_coords(u, v, dpdu, dpdv);
+        Some(rec)
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * cross(&(self.v2 - self.v1), &(self.v3 - self.v1)).norm()
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+}
+
+// emitters are kept whole, anything else is searched for them
+pub fn collect_emitters(objects: &[Arc<Hittable>], out: &mut Vec<Arc<Hittable>>) {
+    for obj in objects {
+        if obj.is_emitter() {
+            out.push(obj.clone());
+        } else {
+            obj.collect_emitters(out);
         }
+    }
+}
 
-        let s = r.ori() - self.v1;
-        let inv_dot = 1.0 / dot_prod;
-        let u = inv_dot * dot(&s, &r_x_e2);
+// moeller-trumbore, returns (t, u, v) where u and v are the barycentric
+// weights of the second and third vertex
+#[inline(always)]
+pub fn intersect_triangle(r: &Ray, ray_t: Interval, v1: Point3, v2: Point3, v3: Point3) -> Option<(f64, f64, f64)> {
+    let e1 = v2 - v1; // edge_1
+    let e2 = v3 - v1;
+    let r_x_e2 = cross(&r.dir(), &e2);
+    let dot_prod = dot(&e1, &r_x_e2); // ray.dir() dot normal vec
 
-        if u > 1.0 || u < 0.0 { return None; } // outside
+    if dot_prod > -f64::EPSILON && dot_prod < f64::EPSILON {
+        return None; // ray is parallel, no intersection
+    }
 
-        let s_x_e1 = cross(&s, &e1);
-        let v = inv_dot * dot(&r.dir(), &s_x_e1);
+    let s = r.ori() - v1;
+    let inv_dot = 1.0 / dot_prod;
+    let u = inv_dot * dot(&s, &r_x_e2);
 
-        if v < 0.0 || (u + v) > 1.0 { return None; } // outside
+    if !(0.0..=1.0).contains(&u) { return None; } // outside
 
-        let t = inv_dot * dot(&e2, &s_x_e1);
+    let s_x_e1 = cross(&s, &e1);
+    let v = inv_dot * dot(&r.dir(), &s_x_e1);
 
-        // if ray_t.interior(t) { // > EPSILON??
-        if t > (ray_t.min() + EPSILON) && t < (ray_t.max() - EPSILON) {
-            let sign = if dot_prod < 0.0 { -1.0 } else { 1.0 };
-            // assert_eq!(dot_prod < -EPSILON, dot(&r.dir(), &cross(&e1, &e2)) < -EPSILON);
-            // ^ why doesn't this work?
-            let mut rec = HitRecord::new( 
-                r,
-                sign * unit_vector(cross(&e1, &e2)), // <- is this correct?
-                r.at(t),
-                t,
-                self.material.clone()
-            );
-            rec.set_outward_normal(); // this has no effect, why?
-            return Some(rec);
-            // change code to include correct normal?
-        } else { return None; }
+    if v < 0.0 || (u + v) > 1.0 { return None; } // outside
+
+    let t = inv_dot * dot(&e2, &s_x_e1);
+
+    // if ray_t.interior(t) { // > EPSILON??
+    if t > (ray_t.min() + f64::EPSILON) && t < (ray_t.max() - f64::EPSILON) {
+        Some((t, u, v))
+    } else { None }
+}
+
+// used for triangles without texture coordinates
+pub const DEFAULT_TRIANGLE_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)];
+
+// interpolates the vertices' texture coordinates at barycentrics (b_u, b_v)
+// and solves for the tangents that map uv space onto the face
+pub fn triangle_surface_coords(p: [Point3; 3], uv: [(f64, f64); 3], b_u: f64, b_v: f64) -> (f64, f64, Vec3, Vec3) {
+    let b_w = 1.0 - b_u - b_v;
+    let u = b_w * uv[0].0 + b_u * uv[1].0 + b_v * uv[2].0;
+    let v = b_w * uv[0].1 + b_u * uv[1].1 + b_v * uv[2].1;
+
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let dp02 = p[0] - p[2];
+    let dp12 = p[1] - p[2];
+    let det = du02 * dv12 - dv02 * du12;
+
+    let degenerate = det.abs() < 1.0e-12;
+    if !degenerate {
+        let inv_det = 1.0 / det;
+        let dpdu = inv_det * (dv12 * dp02 - dv02 * dp12);
+        let dpdv = inv_det * (du02 * dp12 - du12 * dp02);
+        if !cross(&dpdu, &dpdv).near_zero() { return (u, v, dpdu, dpdv); }
     }
+
+    // uvs don't span the face, so any tangent frame will do
+    let (dpdu, dpdv) = coordinate_system(&unit_vector(cross(&(p[1] - p[0]), &(p[2] - p[0]))));
+    (u, v, dpdu, dpdv)
+}
+
+// weights of the second and third vertex for a point spread uniformly
+// over a triangle
+#[inline(always)]
+fn uniform_barycentrics(sampler: &mut Sampler) -> (f64, f64) {
+    let (u1, u2) = sampler.next_2d();
+    let su = u1.sqrt();
+    (u2 * su, 1.0 - su)
+}
+
+// record for a point found by sampling rather than by a ray, facing out
+pub fn surface_record(p: Point3, outward_normal: Vec3, mat: Arc<Material>) -> HitRecord {
+    HitRecord::new(&ray(p + outward_normal, -outward_normal), outward_normal, p, 0.0, mat)
+}
+
+pub fn triangle_record(r: &Ray, t: f64, v1: Point3, v2: Point3, v3: Point3, mat: Arc<Material>) -> HitRecord {
+    // the outward side is the one the vertices wind counter-clockwise around
+    let outward_normal = unit_vector(cross(&(v2 - v1), &(v3 - v1)));
+    let mut rec = HitRecord::new(r, outward_normal, r.at(t), t, mat);
+    rec.set_outward_normal();
+    rec
 }
 
 impl HitRecord {
@@ -145,7 +343,12 @@ impl HitRecord {
             p: po,
             t: tt,
             front_face: dot(&r.dir(), &normal) < 0.0,
-            normal: normal,
+            u: 0.0,
+            v: 0.0,
+            dpdu: vec3(0, 0, 0),
+            dpdv: vec3(0, 0, 0),
+            normal,
+            geo_normal: normal,
             material: mat
         }
         // performance issue?
@@ -153,35 +356,82 @@ impl HitRecord {
 
     pub fn set_outward_normal(&mut self) {
         self.normal =  if self.front_face { self.normal } else { -self.normal };
+        self.geo_normal = if self.front_face { self.geo_normal } else { -self.geo_normal };
+    }
+
+    // call after set_outward_normal, with an interpolated outward normal
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        let mut n = unit_vector(outward_normal);
+        if !self.front_face { n = -n; }
+        // normals that disagree with the winding would shade the back of the face
+        self.normal = if dot(&n, &self.geo_normal) < 0.0 { -n } else { n };
+    }
+
+    pub fn set_surface_coords(&mut self, u: f64, v: f64, dpdu: Vec3, dpdv: Vec3) {
+        self.u = u;
+        self.v = v;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+
+    // starts a new ray just off the surface, on the side `dir` leaves through
+    pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+        const OFFSET: f64 = 1.0e-6;
+        let side = if dot(&dir, &self.geo_normal) < 0.0 { -OFFSET } else { OFFSET };
+        ray(self.p + side * self.geo_normal, dir)
     }
 }
 
 impl Sphere {
     pub fn new(ctr: Point3, rad: f64, material: Arc<Material>) -> Self {
-        Self { center: ctr, radius: rad, material: material }
+        let rvec = vec3(rad, rad, rad);
+        Self { center: ctr, radius: rad, material, bbox: Aabb::from_points(ctr - rvec, ctr + rvec) }
+    }
+
+    // spherical mapping of a point `n` on the unit sphere: u goes around the
+    // y axis starting at -x, v goes from -y up to +y
+    fn surface_coords(&self, n: &Vec3) -> (f64, f64, Vec3, Vec3) {
+        let theta = (-n.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-n.z()).atan2(n.x()) + PI;
+
+        let sin_theta = theta.sin();
+        if sin_theta < 1.0e-8 {
+            // u is undefined at the poles, any tangent frame will do
+            let (dpdu, dpdv) = coordinate_system(n);
+            return (phi / (2.0 * PI), theta / PI, dpdu, dpdv);
+        }
+
+        let cos_theta = -n.y();
+        let dpdu = 2.0 * PI * self.radius * vec3(n.z(), 0, -n.x());
+        let dpdv = PI * self.radius * vec3(n.x() * cos_theta / sin_theta, sin_theta, n.z() * cos_theta / sin_theta);
+
+        (phi / (2.0 * PI), theta / PI, dpdu, dpdv)
     }
 }
 
 impl Triangle {
     pub fn new(a: Point3, b: Point3, c: Point3, mat: Arc<Material>) -> Self {
-        Self { v1: a, v2: b, v3: c, material: mat }
+        let bbox = Aabb::surrounding(&Aabb::from_points(a, b), &Aabb::from_points(c, c));
+        Self { v1: a, v2: b, v3: c, uvs: DEFAULT_TRIANGLE_UVS, material: mat, bbox }
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = uvs;
+        self
     }
 }
 
 impl HittableList {
     pub fn new() -> Self {
-        Self { objects: Vec::new() }
-    }
-
-    pub fn clear(&mut self) {
-        self.objects.clear();
+        Self { objects: Vec::new(), bbox: Aabb::empty() }
     }
 
     pub fn add(&mut self, obj: Arc<Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &obj.aabb());
         self.objects.push(obj);
     }
 
-    pub fn add_front(&mut self, obj: Arc<Hittable>) {
-        self.objects.splice(0..0, [obj]);
+    pub fn into_objects(self) -> Vec<Arc<Hittable>> {
+        self.objects
     }
 }
@@ -0,0 +1,163 @@
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+// how bright a real fixture is in each direction, from an ies lm-63
+// photometric file. vertical angles are measured from straight down (the
+// fixture's nadir) and horizontal ones around it, as in type c photometry
+pub struct IesProfile {
+    vertical: Vec<f64>, // degrees, ascending
+    horizontal: Vec<f64>, // degrees, ascending
+    candela: Vec<f64>, // a row of vertical angles per horizontal angle
+    peak: f64
+}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        // keywords up to the tilt line, then only numbers
+        let mut lines = text.lines();
+        let tilt = lines.by_ref().find(|l| l.trim_start().starts_with("TILT=")).ok_or_else(|| invalid("no TILT line in ies file"))?;
+        let mut numbers = lines.flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty()).map(|s| s.parse::<f64>());
+        let mut next = || -> io::Result<f64> {
+            numbers.next().ok_or_else(|| invalid("truncated ies file"))?.map_err(|_| invalid("bad number in ies file"))
+        };
+
+        match tilt.trim_start()["TILT=".len()..].trim() {
+            "NONE" => {}
+            "INCLUDE" => {
+                // lamp to luminaire geometry, then angles and their factors
+                next()?;
+                let n = next()? as usize;
+                for _ in 0..2 * n { next()?; }
+            }
+            _ => return Err(invalid("tilt data in a separate file isn't supported"))
+        }
+
+        let (_lamps, _lumens, multiplier) = (next()?, next()?, next()?);
+        let (nv, nh) = (next()? as usize, next()? as usize);
+        let photometric_type = next()?;
+        for _ in 0..4 { next()?; } // units and luminous opening
+        for _ in 0..3 { next()?; } // ballast factors and input watts
+        if photometric_type != 1.0 { return Err(invalid("only type c photometry is supported")); }
+        if nv == 0 || nh == 0 { return Err(invalid("ies file has no angles")); }
+
+        let vertical = (0..nv).map(|_| next()).collect::<io::Result<Vec<f64>>>()?;
+        let horizontal = (0..nh).map(|_| next()).collect::<io::Result<Vec<f64>>>()?;
+        let candela = (0..nv * nh).map(|_| next().map(|c| c * multiplier)).collect::<io::Result<Vec<f64>>>()?;
+        let peak = candela.iter().fold(0.0, |m: f64, c| m.max(*c));
+
+        Ok(Self { vertical, horizontal, candela, peak })
+    }
+
+    // brightest intensity in the file, in candela
+    pub fn peak(&self) -> f64 {
+        self.peak
+    }
+
+    // intensity towards `vertical` degrees from the nadir and `horizontal`
+    // degrees around it, relative to the peak
+    pub fn value(&self, vertical: f64, horizontal: f64) -> f64 {
+        if self.peak <= 0.0 { return 0.0; }
+        // nothing is measured outside the vertical angles given, e.g.
+        // above a downlight
+        if vertical < self.vertical[0] || vertical > self.vertical[self.vertical.len() - 1] { return 0.0; }
+
+        // files only cover as much of the circle as the fixture's symmetry
+        // needs
+        let h = horizontal.rem_euclid(360.0);
+        let h = match self.horizontal[self.horizontal.len() - 1] {
+            last if last <= 0.0 => 0.0,
+            last if last <= 90.0 => { let h = h % 180.0; if h > 90.0 { 180.0 - h } else { h } }
+            last if last <= 180.0 => if h > 180.0 { 360.0 - h } else { h },
+            _ => h
+        };
+
+        let (i, fi) = bracket(&self.horizontal, h);
+        let (j, fj) = bracket(&self.vertical, vertical);
+        let nv = self.vertical.len();
+        let at = |i: usize, j: usize| self.candela[i * nv + j];
+        let i1 = (i + 1).min(self.horizontal.len() - 1);
+        let j1 = (j + 1).min(nv - 1);
+
+        let c = (1.0 - fi) * ((1.0 - fj) * at(i, j) + fj * at(i, j1)) + fi * ((1.0 - fj) * at(i1, j) + fj * at(i1, j1));
+        c / self.peak
+    }
+}
+
+// index of the last angle at or below `x` and how far it is to the next,
+// clamped to the ends
+fn bracket(angles: &[f64], x: f64) -> (usize, f64) {
+    let i = angles.partition_point(|a| *a <= x).saturating_sub(1);
+    if i + 1 >= angles.len() || x <= angles[0] { return (i, 0.0); }
+    (i, (x - angles[i]) / (angles[i + 1] - angles[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fixture symmetric about 0-90 degrees, measured every 45 degrees
+    // both ways, with a tilt block to skip and a multiplier of 2
+    const QUADRANT: &str = "IESNA:LM-63-2002
+[TEST] quadrant
+[MANUFAC] nobody
+TILT=INCLUDE
+1
+3
+0 45 90
+1.0, 0.9, 0.8
+1 1000 2.0 3 3 1 1 0.5 0.5 0.0
+1.0 1.0 100
+0 45 90
+0 45 90
+100 50 0
+80 40 0
+60 30 0
+";
+
+    fn near(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1.0e-12
+    }
+
+    #[test]
+    fn parses_header_and_candela() {
+        let ies = IesProfile::parse(QUADRANT).unwrap();
+        assert_eq!(ies.vertical, vec![0.0, 45.0, 90.0]);
+        assert_eq!(ies.horizontal, vec![0.0, 45.0, 90.0]);
+        assert_eq!(ies.candela, vec![200.0, 100.0, 0.0, 160.0, 80.0, 0.0, 120.0, 60.0, 0.0]);
+        assert!(near(ies.peak(), 200.0));
+    }
+
+    #[test]
+    fn interpolates_and_mirrors() {
+        let ies = IesProfile::parse(QUADRANT).unwrap();
+        assert!(near(ies.value(0.0, 0.0), 1.0));
+        assert!(near(ies.value(45.0, 90.0), 0.3));
+        assert!(near(ies.value(22.5, 0.0), 0.75));
+        assert!(near(ies.value(22.5, 22.5), 0.675));
+
+        // a quadrant stands for the whole circle
+        for h in [22.5, 67.5] {
+            for mirrored in [180.0 - h, 180.0 + h, 360.0 - h, -h] {
+                assert!(near(ies.value(30.0, h), ies.value(30.0, mirrored)), "{h} against {mirrored}");
+            }
+        }
+        assert_eq!(ies.value(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        assert!(IesProfile::parse("IESNA:LM-63-2002\n1 1000 1 1 1 1 1 0 0 0\n").is_err());
+        assert!(IesProfile::parse("TILT=lamp.tlt\n").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 1 1 2 1 0 0 0 1 1 100 0 0 5\n").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 2 1 1 1 0 0 0 1 1 100 0 45\n").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 1 1 1 1 0 0 0 1 1 100 0 0 x\n").is_err());
+    }
+}
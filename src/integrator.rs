@@ -0,0 +1,306 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::camera::*;
+use crate::bdpt::*;
+use crate::sppm::*;
+use crate::mlt::*;
+use crate::medium::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+use enum_dispatch::enum_dispatch;
+
+// recursive path tracing with light sampling at every bounce, combined with
+// the material's own sampling by MIS. media are ignored
+pub struct NaivePathIntegrator {
+    pub max_depth: i32
+}
+
+// follows material scattering only, emitters are found by chance
+pub struct BsdfPathIntegrator {
+    pub max_depth: i32
+}
+
+// path tracing with light sampling at every bounce, combined with the
+// material's own sampling by MIS. paths past `rr_depth` bounces are ended
+// at random in proportion to how little they can still carry. media scatter
+// paths between surfaces, and count as bounces where they do
+pub struct PathIntegrator {
+    pub max_depth: i32,
+    pub rr_depth: i32
+}
+
+// emitted light plus one bounce of direct lighting, following mirrors and
+// glass up to max_depth
+pub struct DirectLightingIntegrator {
+    pub max_depth: i32
+}
+
+// fraction of the hemisphere left open within `distance`
+pub struct AmbientOcclusionIntegrator {
+    pub distance: f64
+}
+
+// shading normals mapped from [-1, 1] to [0, 1]
+pub struct NormalsIntegrator;
+
+// distance to the first hit, white at `max_distance` and beyond
+pub struct DepthIntegrator {
+    pub max_distance: f64
+}
+
+#[enum_dispatch]
+pub trait Integrate {
+    // light arriving at the camera along `r`, at the wavelengths `lambda`
+    // carries
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour;
+
+    // one camera sample. integrators that also trace paths from the lights
+    // add what those show through other pixels to `splats`
+    fn sample(&self, r: &Ray, scene: &Scene, _cam: &Camera, lambda: &mut Wavelengths, _splats: &mut Vec<Splat>,
+              sampler: &mut Sampler) -> Colour {
+        self.li(r, scene, lambda, sampler)
+    }
+
+    // integrators that can't work one camera sample at a time fill the
+    // film themselves, one colour per pixel row by row. None leaves the
+    // sampling to the camera
+    fn render(&self, _cam: &Camera, _scene: &Scene) -> Option<Vec<Colour>> {
+        None
+    }
+}
+
+#[enum_dispatch(Integrate)]
+pub enum Integrator {
+    NaivePath(NaivePathIntegrator),
+    BsdfPath(BsdfPathIntegrator),
+    Path(PathIntegrator),
+    Bidirectional(BdptIntegrator),
+    Sppm(SppmIntegrator),
+    Metropolis(MltIntegrator),
+    DirectLighting(DirectLightingIntegrator),
+    AmbientOcclusion(AmbientOcclusionIntegrator),
+    Normals(NormalsIntegrator),
+    Depth(DepthIntegrator)
+}
+
+impl Integrator {
+    // an integrator by the name it's picked with on the command line, set
+    // up for the scenes main renders
+    pub fn by_name(name: &str) -> Option<Self> {
+        let path = PathIntegrator { max_depth: 25, rr_depth: 3 };
+        Some(match name {
+            "naive" => NaivePathIntegrator { max_depth: 25 }.into(),
+            "bsdf" => BsdfPathIntegrator { max_depth: 25 }.into(),
+            "path" => path.into(),
+            "bdpt" => BdptIntegrator { max_depth: 25 }.into(),
+            "sppm" => SppmIntegrator { passes: 64, photons_per_pass: 200_000, initial_radius: 0.002, max_depth: 25 }.into(),
+            "mlt" => MltIntegrator {
+                path,
+                bootstrap_samples: 100_000,
+                chains: 1000,
+                mutations_per_pixel: 100,
+                sigma: 0.01,
+                large_step_probability: 0.3
+            }.into(),
+            "direct" => DirectLightingIntegrator { max_depth: 5 }.into(),
+            "ao" => AmbientOcclusionIntegrator { distance: 1.0 }.into(),
+            "normals" => NormalsIntegrator.into(),
+            "depth" => DepthIntegrator { max_distance: 10.0 }.into(),
+            _ => return None
+        })
+    }
+}
+
+impl Integrate for NaivePathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.ray_clr(r, scene, self.max_depth, None, lambda, sampler)
+    }
+}
+
+impl NaivePathIntegrator {
+    // `last_bounce` is where the last bounce was and the density it chose
+    // `r` with, or None if the lights weren't sampled there (camera rays,
+    // mirrors, glass)
+    fn ray_clr(&self, r: &Ray, scene: &Scene, max_bounces: i32, last_bounce: Option<(Point3, f64)>, lambda: &mut Wavelengths,
+               sampler: &mut Sampler) -> Colour {
+        if max_bounces <= 0 { return colour(0, 0, 0) /*black*/ } // stop calculating rays at max depth reached
+        let Some(rec) = scene.hit(r, interval(0.001, f64::INFINITY)) else { return weighted_background(r, scene, last_bounce, lambda); };
+        let emission = weighted_emission(r, &rec, scene, last_bounce, lambda);
+
+        let Some((scatrd_ray, attenuation)) = rec.material.scatter(r, &rec, lambda, sampler) else { return emission; };
+        let direct = scene.lights.sample_direct(scene, r, &Interaction::Surface(&rec), None, lambda, sampler);
+        let next = direct.map(|_| (rec.p, rec.material.pdf(r, &rec, &scatrd_ray.dir())));
+        let indirect = attenuation * self.ray_clr(&scatrd_ray, scene, max_bounces - 1, next, lambda, sampler);
+        emission + direct.unwrap_or(colour(0, 0, 0)) + indirect
+    }
+}
+
+impl Integrate for BsdfPathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.ray_clr(r, scene, self.max_depth, lambda, sampler)
+    }
+}
+
+impl BsdfPathIntegrator {
+    fn ray_clr(&self, r: &Ray, scene: &Scene, max_bounces: i32, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        if max_bounces <= 0 { return colour(0, 0, 0) /*black*/ } // stop calculating rays at max depth reached
+        if let Some(rec) = scene.hit(r, interval(0.001, f64::INFINITY)) {
+            let emission = rec.material.emitted(r, &rec, lambda);
+            if let Some((scatrd_ray, attenuation)) = rec.material.scatter(r, &rec, lambda, sampler) {
+                return emission + attenuation * self.ray_clr(&scatrd_ray, scene, max_bounces - 1, lambda, sampler);
+            }
+            return emission;
+        }
+
+        scene.background(r, lambda)
+    }
+}
+
+impl Integrate for PathIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        let mut radiance = colour(0, 0, 0);
+        let mut throughput = colour(1, 1, 1); // what the path so far lets through
+        let mut r = *r;
+        // where the last bounce was and the density it chose `r` with, or
+        // None if the lights weren't sampled there (camera rays, mirrors,
+        // glass)
+        let mut last_bounce: Option<(Point3, f64)> = None;
+        let mut medium = scene.medium.clone(); // what `r` travels through
+        let mut depth = 0;
+
+        while depth < self.max_depth {
+            let hit = scene.hit(&r, interval(0.001, f64::INFINITY));
+
+            // the medium may scatter the path before it gets anywhere
+            let mut scattered = None;
+            if let Some(m) = &medium {
+                let s = m.sample(&r, hit.as_ref().map_or(f64::INFINITY, |rec| rec.t), lambda, sampler);
+                throughput = throughput * s.weight;
+                scattered = s.t.map(|t| (r.at(t), m.clone()));
+            }
+
+            let at = match (&scattered, &hit) {
+                (Some((p, m)), _) => Interaction::Medium(*p, m.phase()),
+                (None, Some(rec)) => Interaction::Surface(rec),
+                (None, None) => {
+                    radiance += throughput * weighted_background(&r, scene, last_bounce, lambda);
+                    break;
+                }
+            };
+
+            if let Interaction::Surface(rec) = at {
+                radiance += throughput * weighted_emission(&r, rec, scene, last_bounce, lambda);
+
+                // boundaries of media aren't bounces
+                if rec.material.is_interface() {
+                    medium = scene.medium_after(rec, &r.dir(), medium);
+                    r = rec.spawn_ray(r.dir());
+                    continue;
+                }
+            }
+
+            let Some((scatrd_ray, attenuation)) = at.scatter(&r, lambda, sampler) else { break; };
+            let direct = scene.lights.sample_direct(scene, &r, &at, medium.clone(), lambda, sampler);
+            if let Some(direct) = direct { radiance += throughput * direct; }
+
+            last_bounce = direct.map(|_| (at.p(), at.pdf(&r, &scatrd_ray.dir())));
+            throughput = throughput * attenuation;
+            if let Interaction::Surface(rec) = at { medium = scene.medium_after(rec, &scatrd_ray.dir(), medium); }
+            r = scatrd_ray;
+            depth += 1;
+
+            if depth >= self.rr_depth {
+                // survivors are boosted by the odds they beat, which keeps
+                // the estimate unbiased
+                let survive = throughput.max_component().min(0.95);
+                if survive <= 0.0 || sampler.next_1d() >= survive { break; }
+                throughput /= survive;
+            }
+        }
+
+        radiance
+    }
+}
+
+impl Integrate for DirectLightingIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.ray_clr(r, scene, self.max_depth, None, lambda, sampler)
+    }
+}
+
+impl DirectLightingIntegrator {
+    fn ray_clr(&self, r: &Ray, scene: &Scene, max_bounces: i32, last_bounce: Option<(Point3, f64)>, lambda: &mut Wavelengths,
+               sampler: &mut Sampler) -> Colour {
+        if max_bounces <= 0 { return colour(0, 0, 0); }
+        let Some(rec) = scene.hit(r, interval(0.001, f64::INFINITY)) else { return weighted_background(r, scene, last_bounce, lambda); };
+        let emission = weighted_emission(r, &rec, scene, last_bounce, lambda);
+
+        // past a diffuse bounce only the emitter the bsdf sample lands on counts
+        if last_bounce.is_some() { return emission; }
+
+        let Some((scatrd_ray, attenuation)) = rec.material.scatter(r, &rec, lambda, sampler) else { return emission; };
+        match scene.lights.sample_direct(scene, r, &Interaction::Surface(&rec), None, lambda, sampler) {
+            Some(direct) => {
+                let pdf = rec.material.pdf(r, &rec, &scatrd_ray.dir());
+                emission + direct + attenuation * self.ray_clr(&scatrd_ray, scene, max_bounces - 1, Some((rec.p, pdf)), lambda, sampler)
+            }
+            None => emission + attenuation * self.ray_clr(&scatrd_ray, scene, max_bounces - 1, None, lambda, sampler)
+        }
+    }
+}
+
+impl Integrate for AmbientOcclusionIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        let Some(rec) = scene.hit(r, interval(0.001, f64::INFINITY)) else { return colour(1, 1, 1); };
+
+        // cosine weighted, so the estimate is just the visibility
+        let mut dir = rec.normal + randvec_in_unit_sphere(sampler);
+        if dir.near_zero() { dir = rec.normal; }
+        if dot(&dir, &rec.geo_normal) <= 0.0 { return colour(0, 0, 0); }
+
+        let occluded = scene.hit(&rec.spawn_ray(unit_vector(dir)), interval(0.001, self.distance)).is_some();
+        if occluded { colour(0, 0, 0) } else { colour(1, 1, 1) }
+    }
+}
+
+impl Integrate for NormalsIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _lambda: &mut Wavelengths, _sampler: &mut Sampler) -> Colour {
+        match scene.hit(r, interval(0.001, f64::INFINITY)) {
+            Some(rec) => 0.5 * (rec.normal + 1.0),
+            None => colour(0, 0, 0)
+        }
+    }
+}
+
+impl Integrate for DepthIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, _lambda: &mut Wavelengths, _sampler: &mut Sampler) -> Colour {
+        let d = match scene.hit(r, interval(0.001, f64::INFINITY)) {
+            Some(rec) => (rec.t * r.dir().norm() / self.max_distance).min(1.0),
+            None => 1.0
+        };
+        colour(d, d, d)
+    }
+}
+
+// emission seen at `rec`, MIS weighted if light sampling at the previous
+// bounce could also have found it
+pub fn weighted_emission(r: &Ray, rec: &HitRecord, scene: &Scene, last_bounce: Option<(Point3, f64)>, lambda: &Wavelengths) -> Colour {
+    let emission = rec.material.emitted(r, rec, lambda);
+    match last_bounce {
+        Some((p, pdf)) if !emission.near_zero() => power_heuristic(pdf, scene.lights.pdf_value(scene, &p, r)) * emission,
+        _ => emission
+    }
+}
+
+// the background seen along `r`, weighted the same way
+pub fn weighted_background(r: &Ray, scene: &Scene, last_bounce: Option<(Point3, f64)>, lambda: &Wavelengths) -> Colour {
+    let background = scene.background(r, lambda);
+    match last_bounce {
+        Some((_, pdf)) => power_heuristic(pdf, scene.lights.pdf_background(scene, &unit_vector(r.dir()))) * background,
+        None => background
+    }
+}
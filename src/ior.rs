@@ -0,0 +1,126 @@
+// refractive index of a dielectric, either the same at every wavelength or
+// varying with it, which is what splits white light into colours. the
+// coefficients are for wavelengths in micrometres, as they're tabulated
+#[derive(Clone, Copy)]
+pub enum Ior {
+    Constant(f64),
+    Cauchy { a: f64, b: f64 }, // a + b / l^2
+    Sellmeier { b: [f64; 3], c: [f64; 3] } // n^2 = 1 + sum of b_i l^2 / (l^2 - c_i)
+}
+
+impl Ior {
+    // schott n-bk7, the usual optical crown glass
+    pub fn bk7() -> Self {
+        Ior::Sellmeier { b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653] }
+    }
+
+    // malitson 1965
+    pub fn fused_silica() -> Self {
+        Ior::Sellmeier { b: [0.6961663, 0.4079426, 0.8974794], c: [0.00467914826, 0.0135120631, 97.9340025] }
+    }
+
+    // peter 1923
+    pub fn diamond() -> Self {
+        Ior::Sellmeier { b: [0.3306, 4.3356, 0.0], c: [0.030625, 0.011236, 0.0] }
+    }
+
+    // index at `lambda` nm
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l2 = (lambda * 1.0e-3).powi(2);
+        match self {
+            Ior::Constant(mu) => *mu,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()
+        }
+    }
+}
+
+// complex index of refraction of a metal, measured at wavelengths across
+// the visible range, as (nm, eta, k) in order of wavelength
+#[derive(Clone, Copy)]
+pub struct Conductor {
+    pub samples: &'static [(f64, f64, f64)]
+}
+
+impl Conductor {
+    // johnson and christy 1972
+    pub fn gold() -> Self {
+        Self { samples: &[
+            (354.2, 1.50, 1.866), (367.9, 1.48, 1.895), (381.5, 1.46, 1.933), (397.4, 1.47, 1.952), (413.3, 1.46, 1.958),
+            (430.5, 1.45, 1.948), (450.9, 1.38, 1.914), (471.4, 1.31, 1.849), (495.9, 1.04, 1.833), (520.9, 0.62, 2.081),
+            (548.6, 0.43, 2.455), (582.1, 0.29, 2.863), (616.8, 0.21, 3.272), (659.5, 0.14, 3.697), (704.5, 0.13, 4.103),
+            (756.0, 0.14, 4.542), (821.1, 0.16, 5.083), (892.0, 0.17, 5.663)
+        ] }
+    }
+
+    // johnson and christy 1972
+    pub fn copper() -> Self {
+        Self { samples: &[
+            (354.2, 1.37, 1.945), (367.9, 1.36, 2.025), (381.5, 1.33, 2.079), (397.4, 1.32, 2.116), (413.3, 1.28, 2.227),
+            (430.5, 1.25, 2.305), (450.9, 1.24, 2.397), (471.4, 1.25, 2.483), (495.9, 1.22, 2.564), (520.9, 1.18, 2.608),
+            (548.6, 1.02, 2.577), (582.1, 0.70, 2.704), (616.8, 0.30, 3.205), (659.5, 0.22, 3.747), (704.5, 0.21, 4.205),
+            (756.0, 0.24, 4.665), (821.1, 0.26, 5.180), (892.0, 0.30, 5.768)
+        ] }
+    }
+
+    // rakic 1995
+    pub fn aluminium() -> Self {
+        Self { samples: &[
+            (350.0, 0.38, 4.25), (400.0, 0.49, 4.86), (450.0, 0.62, 5.47), (500.0, 0.77, 6.08), (550.0, 0.96, 6.69),
+            (600.0, 1.20, 7.26), (650.0, 1.47, 7.79), (700.0, 1.83, 8.31), (750.0, 2.40, 8.62), (800.0, 2.80, 8.45),
+            (850.0, 2.58, 8.30)
+        ] }
+    }
+
+    // eta and k at `lambda` nm, straight between samples and held past the
+    // ends
+    pub fn at(&self, lambda: f64) -> (f64, f64) {
+        let s = self.samples;
+        let i = s.partition_point(|&(l, _, _)| l < lambda);
+        if i == 0 { return (s[0].1, s[0].2); }
+        if i == s.len() { return (s[i - 1].1, s[i - 1].2); }
+
+        let ((l0, eta0, k0), (l1, eta1, k1)) = (s[i - 1], s[i]);
+        let t = (lambda - l0) / (l1 - l0);
+        (eta0 + t * (eta1 - eta0), k0 + t * (k1 - k0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // catalogue indices at the helium d, hydrogen f and hydrogen c lines
+    #[test]
+    fn matches_published_indices() {
+        let cases = [
+            (Ior::bk7(), [(587.56, 1.5168), (486.13, 1.5224), (656.27, 1.5143)], 1.0e-4),
+            (Ior::fused_silica(), [(587.56, 1.4585), (486.13, 1.4632), (656.27, 1.4564)], 1.0e-4),
+            (Ior::diamond(), [(587.56, 2.4175), (486.13, 2.4354), (656.27, 2.4099)], 1.0e-3)
+        ];
+        for (ior, lines, tolerance) in cases {
+            for (lambda, n) in lines {
+                assert!((ior.at(lambda) - n).abs() < tolerance, "{} at {lambda} nm, not {n}", ior.at(lambda));
+            }
+        }
+        assert_eq!(Ior::Constant(1.5).at(400.0), 1.5);
+    }
+
+    #[test]
+    fn conductor_interpolates_and_holds_at_the_ends() {
+        let gold = Conductor::gold();
+        assert_eq!(gold.at(548.6), (0.43, 2.455));
+        let (eta, k) = gold.at(0.5 * (548.6 + 582.1));
+        assert!((eta - 0.36).abs() < 1.0e-9 && (k - 2.659).abs() < 1.0e-9);
+        assert_eq!(gold.at(300.0), (1.50, 1.866));
+        assert_eq!(Conductor::aluminium().at(900.0), (2.58, 8.30));
+    }
+
+    // normal dispersion, bending blue more than red
+    #[test]
+    fn index_falls_with_wavelength() {
+        for ior in [Ior::bk7(), Ior::fused_silica(), Ior::diamond(), Ior::Cauchy { a: 1.5, b: 0.004 }] {
+            assert!(ior.at(400.0) > ior.at(550.0) && ior.at(550.0) > ior.at(700.0));
+        }
+    }
+}
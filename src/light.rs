@@ -0,0 +1,544 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::bvh::*;
+use crate::sampler::*;
+use crate::scene::*;
+use crate::medium::*;
+use crate::background::*;
+use crate::ies::*;
+use crate::lightbvh::*;
+use crate::spectrum::*;
+use enum_dispatch::enum_dispatch;
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
+
+// every emitting primitive in a scene, and the lights that aren't
+// primitives, for sampling direct light
+pub struct Lights {
+    emitters: Vec<Arc<Hittable>>,
+    tree: BvhTree, // finds which emitter a ray reached
+    delta: Vec<Light>, // with a position, in the light tree after the emitters
+    distant: Vec<Light>, // picked between like the background
+    bounds: Vec<LightBounds>,
+    bvh: OnceLock<LightBvh>, // built the first time it's needed, once every light is in
+    world: Aabb // for aiming light from far away at the scene
+}
+
+// where sample_point landed
+pub enum LightPoint<'a> {
+    Surface(HitRecord), // on an emitter, seen from outside
+    Delta(&'a Light)
+}
+
+// what sample_direct picked
+enum Chosen<'a> {
+    Emitter(&'a Arc<Hittable>),
+    Delta(&'a Light),
+    Background
+}
+
+// lights with no size, which rays can't hit. only next event estimation
+// and paths traced out of the lights find them, so integrators with
+// neither (bsdf) leave them out
+#[enum_dispatch]
+pub trait Radiate {
+    // unit direction from `p` to the light, the light arriving at `p` along
+    // it and how far away the light is
+    fn incident(&self, p: &Point3) -> (Vec3, Colour, f64);
+
+    // None for lights infinitely far away
+    fn bounds(&self) -> Option<LightBounds>;
+
+    // a ray leaving the light. lights infinitely far away aim it at
+    // somewhere in `target`, starting from outside `world`
+    fn emit(&self, world: &Aabb, target: &Aabb, lambda: &Wavelengths, sampler: &mut Sampler) -> Option<EmissionSample<'_>>;
+
+    // solid angle density of emit leaving along unit vector `dir`, for
+    // lights with a position
+    fn pdf_emit(&self, dir: &Vec3) -> f64;
+}
+
+#[enum_dispatch(Radiate)]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight)
+}
+
+// the same in every direction unless given a profile, which hangs
+// pointing down -y
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Colour, // per steradian, the profile's peak if there is one
+    pub profile: Option<Arc<IesProfile>>
+}
+
+// a point light shining into a cone, fading out between `falloff_start`
+// and `cone_angle` degrees off its axis. a profile's nadir lies along the
+// axis
+pub struct SpotLight {
+    pub position: Point3,
+    pub direction: Vec3,
+    pub intensity: Colour,
+    pub cone_angle: f64,
+    pub falloff_start: f64,
+    pub profile: Option<Arc<IesProfile>>
+}
+
+// parallel light from very far away, like the sun
+pub struct DirectionalLight {
+    pub direction: Vec3, // the way the light travels
+    pub irradiance: Colour // on a surface facing it
+}
+
+// a ray leaving a light, for tracing light forwards
+pub struct EmissionSample<'a> {
+    pub rec: Option<HitRecord>, // where it starts on an emitter, seen from outside
+    pub light: Option<&'a Light>, // or the light with no size it left
+    pub ray: Ray, // unit direction
+    pub emitted: Colour, // radiance, intensity for point lights or irradiance for distant ones
+    pub pdf_pos: f64, // per unit area, across the beam for distant lights, and for point lights just the chance of picking them
+    pub pdf_dir: f64 // per unit solid angle, 1 for distant lights
+}
+
+impl Lights {
+    pub fn new(world: &Hittable) -> Self {
+        let mut emitters = Vec::new();
+        world.collect_emitters(&mut emitters);
+
+        let bounds: Vec<Aabb> = emitters.iter().map(|e| e.aabb()).collect();
+        let (tree, order) = BvhTree::new(&bounds);
+        let emitters: Vec<Arc<Hittable>> = order.into_iter().map(|i| emitters[i].clone()).collect();
+
+        // seeded so the same scene always gets the same tree
+        let mut sampler = Sampler::Independent(IndependentSampler::seeded(0));
+        let bounds: Vec<LightBounds> = emitters.iter().map(|e| emitter_bounds(e, &mut sampler)).collect();
+
+        Self { emitters, tree, delta: Vec::new(), distant: Vec::new(), bounds, bvh: OnceLock::new(), world: world.aabb() }
+    }
+
+    pub fn add(&mut self, light: Light) {
+        match light.bounds() {
+            Some(bounds) => {
+                self.delta.push(light);
+                self.bounds.push(bounds);
+                self.bvh = OnceLock::new();
+            }
+            None => self.distant.push(light)
+        }
+    }
+
+
+    // one sample estimate of the light reaching `at` straight from an
+    // emitter or the background and leaving back along `r_in`, MIS weighted
+    // against the material's or phase function's own sampling. `medium` is
+    // the one around `at` on the side `r_in` came from. None if the material
+    // can't be lit this way
+    pub fn sample_direct(&self, scene: &Scene, r_in: &Ray, at: &Interaction, medium: Option<Arc<Medium>>,
+                         lambda: &Wavelengths, sampler: &mut Sampler) -> Option<Colour> {
+        let black = Some(colour(0, 0, 0));
+        let p = at.p();
+        let Some((chosen, pmf)) = self.choose(scene, &p, sampler) else { return black; };
+        let light = match chosen {
+            Chosen::Emitter(light) => Some(light),
+            Chosen::Delta(light) => return sample_delta(light, scene, r_in, at, medium, lambda, sampler).map(|c| c / pmf),
+            Chosen::Background => None
+        };
+        let background = if light.is_none() { scene.background.sample(sampler) } else { None };
+
+        let dir = match (light, &background) {
+            (Some(light), _) => light.random(&p, sampler),
+            (None, Some((dir, _, _))) => *dir,
+            (None, None) => return black
+        };
+        if dir.near_zero() { return black; }
+        let dir = unit_vector(dir);
+
+        let f = at.eval(r_in, &dir, lambda)?;
+        if f.near_zero() { return black; }
+
+        let shadow = at.spawn_ray(dir);
+        let (emitted, light_pdf, t_max) = match (light, background) {
+            (Some(light), _) => {
+                let Some(lrec) = light.hit(&shadow, interval(0.001, f64::INFINITY)) else { return black; };
+                (lrec.material.emitted(&shadow, &lrec, lambda), light.pdf_value(&p, &dir), lrec.t * (1.0 - 1.0e-6))
+            }
+            (None, Some((_, radiance, pdf))) => (lambda.spectral(radiance), pdf, f64::INFINITY),
+            (None, None) => return black
+        };
+        let light_pdf = light_pdf * pmf;
+        if light_pdf <= 0.0 || emitted.near_zero() { return black; }
+
+        // anything in between blocks it, media dim it
+        let tr = scene.transmittance(&shadow, t_max, far_medium(scene, at, &dir, medium), lambda, sampler);
+        if tr.near_zero() { return black; }
+
+        let weight = power_heuristic(light_pdf, at.pdf(r_in, &dir));
+        Some(weight * tr * f * emitted / light_pdf)
+    }
+
+    // density with which sample_direct at `p` would have picked the
+    // direction of `r`, a ray leaving it, given that `r` reaches an emitter
+    // before anything else
+    pub fn pdf_value(&self, scene: &Scene, p: &Point3, r: &Ray) -> f64 {
+        match self.find(r) {
+            Some(i) => {
+                let pmf = (1.0 - self.p_infinite(scene)) * self.bvh().pmf(p, i);
+                if pmf <= 0.0 { return 0.0; }
+                pmf * self.emitters[i].pdf_value(p, &r.dir())
+            }
+            None => 0.0
+        }
+    }
+
+    // the same for a ray along unit vector `dir` that leaves the scene
+    pub fn pdf_background(&self, scene: &Scene, dir: &Vec3) -> f64 {
+        if !scene.background.is_sampled() { return 0.0; }
+        self.p_infinite(scene) / self.infinite(scene) as f64 * scene.background.pdf(dir)
+    }
+
+    // a point spread over all the emitters' surfaces, or a light with no
+    // size, each light as likely as the next. the density is per unit area,
+    // and just the chance of picking the light for ones with no size
+    pub fn sample_point(&self, sampler: &mut Sampler) -> Option<(LightPoint<'_>, f64)> {
+        let n = self.emitters.len() + self.delta.len();
+        if n == 0 { return None; }
+
+        let k = pick(n, sampler);
+        let Some(light) = self.emitters.get(k) else {
+            return Some((LightPoint::Delta(&self.delta[k - self.emitters.len()]), 1.0 / n as f64));
+        };
+        let rec = light.sample_surface(sampler)?;
+        Some((LightPoint::Surface(rec), 1.0 / (light.area() * n as f64)))
+    }
+
+    // a ray leaving a light, starting where sample_point would. emitters
+    // send it in a cosine weighted direction on a side that glows. lights
+    // infinitely far away have nowhere to start from, but can be picked too
+    // when `distant` is given, sending light from outside the scene at that
+    // part of it
+    pub fn sample_emission(&self, distant: Option<&Aabb>, lambda: &Wavelengths, sampler: &mut Sampler) -> Option<EmissionSample<'_>> {
+        let positioned = self.emitters.len() + self.delta.len();
+        if let Some(target) = distant.filter(|_| !self.distant.is_empty()) {
+            let n = positioned + self.distant.len();
+            let k = pick(n, sampler);
+            if k >= positioned {
+                let light = &self.distant[k - positioned];
+                let e = light.emit(&self.world, target, lambda, sampler)?;
+                return Some(EmissionSample { light: Some(light), pdf_pos: e.pdf_pos / n as f64, ..e });
+            }
+        }
+
+        let (point, pdf_pos) = self.sample_point(sampler)?;
+        let share = if distant.is_some() { positioned as f64 / (positioned + self.distant.len()) as f64 } else { 1.0 };
+        let pdf_pos = pdf_pos * share;
+
+        let rec = match point {
+            LightPoint::Surface(rec) => rec,
+            LightPoint::Delta(light) => {
+                let e = light.emit(&self.world, &self.world, lambda, sampler)?;
+                return Some(EmissionSample { light: Some(light), pdf_pos, ..e });
+            }
+        };
+
+        let n = rec.geo_normal;
+        let side = match emitting_sides(&rec) {
+            (true, true) => if sampler.next_1d() < 0.5 { n } else { -n },
+            (true, false) => n,
+            (false, true) => -n,
+            (false, false) => return None
+        };
+
+        let dir = unit_vector(side + randvec_in_unit_sphere(sampler));
+        let pdf_dir = emission_pdf(&rec, &dir);
+        let emitted = emitted_towards(&rec, &dir, lambda);
+        if pdf_dir <= 0.0 || emitted.near_zero() { return None; }
+
+        Some(EmissionSample { ray: rec.spawn_ray(dir), rec: Some(rec), light: None, emitted, pdf_pos, pdf_dir })
+    }
+
+    // density with which sample_point picks the spot `r` reaches, given
+    // that `r` reaches an emitter before anything else
+    pub fn pdf_point(&self, r: &Ray) -> f64 {
+        let n = self.emitters.len() + self.delta.len();
+        match self.find(r) {
+            Some(i) if self.emitters[i].area() > 0.0 => 1.0 / (self.emitters[i].area() * n as f64),
+            _ => 0.0
+        }
+    }
+
+    // the lights infinitely far away, which nothing can hit or start from
+    pub fn distant(&self) -> &[Light] {
+        &self.distant
+    }
+
+    // a light to sample for a point at `p` and the probability it was
+    // picked with. lights at infinity are picked uniformly, each as likely
+    // as the whole tree, and the tree favours what's likely to light `p`
+    fn choose(&self, scene: &Scene, p: &Point3, sampler: &mut Sampler) -> Option<(Chosen<'_>, f64)> {
+        let p_infinite = self.p_infinite(scene);
+        let u = sampler.next_1d();
+
+        if u < p_infinite {
+            let infinite = self.infinite(scene);
+            let k = ((u / p_infinite * infinite as f64) as usize).min(infinite - 1);
+            let chosen = match self.distant.get(k) { Some(light) => Chosen::Delta(light), None => Chosen::Background };
+            return Some((chosen, p_infinite / infinite as f64));
+        }
+
+        let (i, pmf) = self.bvh().sample(p, (u - p_infinite) / (1.0 - p_infinite))?;
+        let chosen = match self.emitters.get(i) {
+            Some(light) => Chosen::Emitter(light),
+            None => Chosen::Delta(&self.delta[i - self.emitters.len()])
+        };
+        Some((chosen, (1.0 - p_infinite) * pmf))
+    }
+
+    // how many lights are infinitely far away
+    fn infinite(&self, scene: &Scene) -> usize {
+        self.distant.len() + usize::from(scene.background.is_sampled())
+    }
+
+    fn p_infinite(&self, scene: &Scene) -> f64 {
+        let infinite = self.infinite(scene) as f64;
+        if infinite == 0.0 { 0.0 } else if self.bounds.is_empty() { 1.0 } else { infinite / (infinite + 1.0) }
+    }
+
+    fn bvh(&self) -> &LightBvh {
+        self.bvh.get_or_init(|| LightBvh::new(&self.bounds))
+    }
+
+    // index of the closest emitter along `r`
+    fn find(&self, r: &Ray) -> Option<usize> {
+        if self.emitters.is_empty() { return None; }
+
+        let mut closest: Option<usize> = None;
+        self.tree.hit(r, interval(0.001, f64::INFINITY), |i, t| {
+            let rec = self.emitters[i].hit(r, t)?;
+            closest = Some(i);
+            Some(rec)
+        });
+        closest
+    }
+}
+
+impl EmissionSample<'_> {
+    // light the ray carries over the density it was picked with
+    pub fn power(&self) -> Colour {
+        let cosine = self.rec.as_ref().map_or(1.0, |rec| dot(&self.ray.dir(), &rec.geo_normal).abs());
+        self.emitted * (cosine / (self.pdf_pos * self.pdf_dir))
+    }
+}
+
+// an index below `n`, uniformly
+fn pick(n: usize, sampler: &mut Sampler) -> usize {
+    ((sampler.next_1d() * n as f64) as usize).min(n - 1)
+}
+
+// light from a delta light, which only ever comes from the one direction
+// so there's nothing to weight it against
+fn sample_delta(light: &Light, scene: &Scene, r_in: &Ray, at: &Interaction, medium: Option<Arc<Medium>>,
+                lambda: &Wavelengths, sampler: &mut Sampler) -> Option<Colour> {
+    let (dir, li, dist) = light.incident(&at.p());
+    let f = at.eval(r_in, &dir, lambda)?;
+    if f.near_zero() || li.near_zero() { return Some(colour(0, 0, 0)); }
+
+    let tr = scene.transmittance(&at.spawn_ray(dir), dist, far_medium(scene, at, &dir, medium), lambda, sampler);
+    Some(tr * f * lambda.spectral(li))
+}
+
+// the medium light arriving along `dir` travels through, which for light
+// coming through a surface is the one on the other side
+fn far_medium(scene: &Scene, at: &Interaction, dir: &Vec3, medium: Option<Arc<Medium>>) -> Option<Arc<Medium>> {
+    match at {
+        Interaction::Surface(rec) => scene.medium_after(rec, dir, medium),
+        Interaction::Medium(..) => medium
+    }
+}
+
+// bounds for an emitter from a few points on it. flat ones keep their one
+// normal, while curved ones and ones glowing both ways could face anywhere
+fn emitter_bounds(emitter: &Hittable, sampler: &mut Sampler) -> LightBounds {
+    const SAMPLES: usize = 16;
+    let mut radiance = 0.0;
+    let mut normals: Vec<Vec3> = Vec::new();
+
+    for _ in 0..SAMPLES {
+        let Some(rec) = emitter.sample_surface(sampler) else { continue; };
+        let outward = if rec.front_face { rec.geo_normal } else { -rec.geo_normal };
+        for n in [outward, -outward] {
+            let l = emitted_towards(&rec, &n, &Wavelengths::RGB).luminance();
+            if l > 0.0 { radiance += l; normals.push(n); }
+        }
+    }
+
+    let flat = normals.iter().all(|n| dot(n, &normals[0]) > 1.0 - 1.0e-9);
+    let (w, cos_theta_o) = match normals.first() {
+        Some(n) if flat => (*n, 1.0),
+        _ => (vec3(0, 0, 1), -1.0)
+    };
+    let phi = PI * emitter.area() * radiance / SAMPLES as f64;
+    LightBounds { bounds: emitter.aabb(), phi, w, cos_theta_o, cos_theta_e: 0.0 }
+}
+
+impl Radiate for PointLight {
+    fn incident(&self, p: &Point3) -> (Vec3, Colour, f64) {
+        let to_light = self.position - *p;
+        let dist = to_light.norm();
+        let dir = to_light / dist;
+        (dir, self.towards(&-dir) / (dist * dist), dist)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let phi = 4.0 * PI * self.intensity.luminance();
+        Some(LightBounds { bounds: Aabb::from_points(self.position, self.position), phi, w: vec3(0, 0, 1), cos_theta_o: -1.0, cos_theta_e: 0.0 })
+    }
+
+    fn emit(&self, _world: &Aabb, _target: &Aabb, lambda: &Wavelengths, sampler: &mut Sampler) -> Option<EmissionSample<'_>> {
+        let dir = randvec_in_unit_sphere(sampler);
+        let emitted = lambda.spectral(self.towards(&dir));
+        if emitted.near_zero() { return None; }
+        Some(EmissionSample { rec: None, light: None, ray: ray(self.position, dir), emitted, pdf_pos: 1.0, pdf_dir: self.pdf_emit(&dir) })
+    }
+
+    fn pdf_emit(&self, _dir: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+impl PointLight {
+    // intensity towards unit vector `out`
+    fn towards(&self, out: &Vec3) -> Colour {
+        let shape = self.profile.as_ref().map_or(1.0, |ies| profile_value(ies, &vec3(0, -1, 0), out));
+        shape * self.intensity
+    }
+}
+
+impl Radiate for SpotLight {
+    fn incident(&self, p: &Point3) -> (Vec3, Colour, f64) {
+        let to_light = self.position - *p;
+        let dist = to_light.norm();
+        let dir = to_light / dist;
+        (dir, self.towards(&-dir) / (dist * dist), dist)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // light inside the cone, counting the fading edge as half
+        let (cos_total, cos_start) = (self.cone_angle.to_radians().cos(), self.falloff_start.to_radians().cos());
+        let phi = 2.0 * PI * (1.0 - 0.5 * (cos_total + cos_start)) * self.intensity.luminance();
+        Some(LightBounds {
+            bounds: Aabb::from_points(self.position, self.position),
+            phi,
+            w: unit_vector(self.direction),
+            cos_theta_o: 1.0,
+            cos_theta_e: cos_total
+        })
+    }
+
+    // uniformly over the cone, fading edge and all
+    fn emit(&self, _world: &Aabb, _target: &Aabb, lambda: &Wavelengths, sampler: &mut Sampler) -> Option<EmissionSample<'_>> {
+        let dir = randvec_in_cone(&unit_vector(self.direction), self.cone_angle.to_radians().cos(), sampler);
+        let (emitted, pdf_dir) = (lambda.spectral(self.towards(&dir)), self.pdf_emit(&dir));
+        if emitted.near_zero() || pdf_dir <= 0.0 { return None; }
+        Some(EmissionSample { rec: None, light: None, ray: ray(self.position, dir), emitted, pdf_pos: 1.0, pdf_dir })
+    }
+
+    fn pdf_emit(&self, dir: &Vec3) -> f64 {
+        let cos_total = self.cone_angle.to_radians().cos();
+        if cos_total >= 1.0 || dot(dir, &unit_vector(self.direction)) < cos_total { return 0.0; }
+        1.0 / (2.0 * PI * (1.0 - cos_total))
+    }
+}
+
+impl SpotLight {
+    // intensity towards unit vector `out`
+    fn towards(&self, out: &Vec3) -> Colour {
+        let axis = unit_vector(self.direction);
+
+        // smoothstep between the edge of the cone and where it starts to fade
+        let cos_theta = dot(out, &axis);
+        let (cos_total, cos_start) = (self.cone_angle.to_radians().cos(), self.falloff_start.to_radians().cos());
+        let falloff = if cos_start <= cos_total {
+            if cos_theta >= cos_total { 1.0 } else { 0.0 }
+        } else {
+            let t = ((cos_theta - cos_total) / (cos_start - cos_total)).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        let shape = self.profile.as_ref().map_or(1.0, |ies| profile_value(ies, &axis, out));
+        falloff * shape * self.intensity
+    }
+}
+
+impl Radiate for DirectionalLight {
+    fn incident(&self, _p: &Point3) -> (Vec3, Colour, f64) {
+        (-unit_vector(self.direction), self.irradiance, f64::INFINITY)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    // from a disc facing the light that covers the sphere around `target`,
+    // moved back along the light until it's outside the sphere around
+    // `world` so nothing in the scene is skipped
+    fn emit(&self, world: &Aabb, target: &Aabb, lambda: &Wavelengths, sampler: &mut Sampler) -> Option<EmissionSample<'_>> {
+        let radius = 0.5 * target.diagonal().norm();
+        let back = (world.centroid() - target.centroid()).norm() + 0.5 * world.diagonal().norm();
+        if target.is_empty() || !(radius > 0.0 && radius.is_finite() && back.is_finite()) { return None; }
+
+        let dir = unit_vector(self.direction);
+        let (s, t) = coordinate_system(&dir);
+        let d = randvec_in_unit_disc(sampler);
+        let origin = target.centroid() + radius * (d.x() * s + d.y() * t) - back.max(radius) * dir;
+
+        let pdf_pos = 1.0 / (PI * radius * radius);
+        Some(EmissionSample { rec: None, light: None, ray: ray(origin, dir), emitted: lambda.spectral(self.irradiance), pdf_pos, pdf_dir: 1.0 })
+    }
+
+    fn pdf_emit(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+// an ies profile's intensity towards unit vector `out`, for a fixture with
+// its nadir along unit vector `nadir`
+fn profile_value(ies: &IesProfile, nadir: &Vec3, out: &Vec3) -> f64 {
+    let (s, t) = coordinate_system(nadir);
+    let vertical = dot(out, nadir).clamp(-1.0, 1.0).acos().to_degrees();
+    let horizontal = dot(out, &t).atan2(dot(out, &s)).to_degrees();
+    ies.value(vertical, horizontal)
+}
+
+// light leaving an emitter's surface towards `dir`, whichever side the
+// record was found from
+pub fn emitted_towards(rec: &HitRecord, dir: &Vec3, lambda: &Wavelengths) -> Colour {
+    let outward = if rec.front_face { rec.geo_normal } else { -rec.geo_normal };
+    let mut lrec = rec.clone();
+    lrec.front_face = dot(dir, &outward) > 0.0;
+    if lrec.front_face != rec.front_face {
+        lrec.normal = -lrec.normal;
+        lrec.geo_normal = -lrec.geo_normal;
+    }
+    lrec.material.emitted(&ray(rec.p + *dir, -*dir), &lrec, lambda)
+}
+
+// whether the outside and inside of an emitter glow
+pub fn emitting_sides(rec: &HitRecord) -> (bool, bool) {
+    let outward = if rec.front_face { rec.geo_normal } else { -rec.geo_normal };
+    (!emitted_towards(rec, &outward, &Wavelengths::RGB).near_zero(), !emitted_towards(rec, &-outward, &Wavelengths::RGB).near_zero())
+}
+
+// solid angle density of sample_emission leaving along unit vector `dir`
+pub fn emission_pdf(rec: &HitRecord, dir: &Vec3) -> f64 {
+    let sides = match emitting_sides(rec) {
+        (true, true) => 2.0,
+        (false, false) => return 0.0,
+        _ => 1.0
+    };
+    if emitted_towards(rec, dir, &Wavelengths::RGB).near_zero() { return 0.0; }
+    dot(dir, &rec.geo_normal).abs() / (PI * sides)
+}
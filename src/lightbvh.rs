@@ -0,0 +1,291 @@
+use crate::vec3::*;
+use crate::util::*;
+use std::f64::consts::PI;
+
+const BINS: usize = 12;
+
+// what a light, or a group of them, can give: where it is, how much power
+// it puts out and which way. normals lie within acos(cos_theta_o) of `w`,
+// and light leaves within acos(cos_theta_e) of a normal
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub phi: f64,
+    pub w: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64
+}
+
+struct LightNode {
+    bounds: LightBounds,
+    offset: usize, // light for leaves, second child for interior nodes
+    leaf: bool
+}
+
+// a tree over lights' bounds for picking one in proportion to roughly how
+// much it lights a point (conty and kulla 2018), so that thousands of
+// emitting triangles don't leave each point lit by the wrong few
+pub struct LightBvh {
+    nodes: Vec<LightNode>,
+    parents: Vec<usize>,
+    leaves: Vec<Option<usize>> // node of each light, None for lights giving nothing
+}
+
+impl LightBounds {
+    // an upper bound on the light reaching `p` from anywhere in the bounds
+    pub fn importance(&self, p: &Point3) -> f64 {
+        if self.phi <= 0.0 { return 0.0; }
+
+        // clamped so points inside the bounds don't blow up
+        let pc = self.bounds.centroid();
+        let d2 = (*p - pc).norm_sq().max(0.5 * self.bounds.diagonal().norm());
+        let wi = if (*p - pc).near_zero() { self.w } else { unit_vector(*p - pc) };
+
+        let cos_theta_w = dot(&self.w, &wi);
+        let sin_theta_w = (1.0 - cos_theta_w * cos_theta_w).max(0.0).sqrt();
+        let sin_theta_o = (1.0 - self.cos_theta_o * self.cos_theta_o).max(0.0).sqrt();
+
+        // directions to `p` from anywhere in the bounds
+        let radius_sq = 0.25 * self.bounds.diagonal().norm_sq();
+        let dist_sq = (*p - pc).norm_sq();
+        let cos_theta_b = if dist_sq < radius_sq { -1.0 } else { (1.0 - radius_sq / dist_sq).max(0.0).sqrt() };
+        let sin_theta_b = (1.0 - cos_theta_b * cos_theta_b).max(0.0).sqrt();
+
+        // the smallest angle between a normal and a direction to `p`
+        let (cos_x, sin_x) = cos_sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let (cos_theta, _) = cos_sin_sub_clamped(sin_x, cos_x, sin_theta_b, cos_theta_b);
+        if cos_theta <= self.cos_theta_e { return 0.0; }
+
+        self.phi * cos_theta / d2
+    }
+
+    fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+        if a.phi <= 0.0 { return *b; }
+        if b.phi <= 0.0 { return *a; }
+
+        let (w, cos_theta_o) = cone_union(&a.w, a.cos_theta_o, &b.w, b.cos_theta_o);
+        LightBounds {
+            bounds: Aabb::surrounding(&a.bounds, &b.bounds),
+            phi: a.phi + b.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e)
+        }
+    }
+
+    // how bad a group is to have to pick within: power spread over a big
+    // box and a wide range of directions
+    fn cost(&self, axis: usize) -> f64 {
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+        let theta_w = (theta_o + theta_e).min(PI);
+        let sin_theta_o = theta_o.sin();
+        let m_omega = 2.0 * PI * (1.0 - self.cos_theta_o)
+            + PI / 2.0 * (2.0 * theta_w * sin_theta_o - (theta_o - 2.0 * theta_w).cos() - 2.0 * theta_o * sin_theta_o + self.cos_theta_o);
+
+        // long thin groups are worse across than along
+        let d = self.bounds.diagonal();
+        let kr = if d[axis] > 0.0 { d.max_component() / d[axis] } else { 1.0 };
+        self.phi * m_omega * kr * self.bounds.surface_area()
+    }
+}
+
+impl LightBvh {
+    pub fn new(bounds: &[LightBounds]) -> Self {
+        let mut lights: Vec<(usize, LightBounds)> = bounds.iter().copied().enumerate().filter(|(_, b)| b.phi > 0.0).collect();
+        let mut bvh = Self { nodes: Vec::new(), parents: Vec::new(), leaves: vec![None; bounds.len()] };
+        if !lights.is_empty() { bvh.build(&mut lights, usize::MAX); }
+        bvh
+    }
+
+    // a light index for `p`, picked with the returned probability, going
+    // down the tree with `u`
+    pub fn sample(&self, p: &Point3, mut u: f64) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() || self.nodes[0].bounds.importance(p) <= 0.0 { return None; }
+
+        let (mut idx, mut pmf) = (0, 1.0);
+        loop {
+            let node = &self.nodes[idx];
+            if node.leaf { return Some((node.offset, pmf)); }
+
+            let (left, right) = (idx + 1, node.offset);
+            let (il, ir) = (self.nodes[left].bounds.importance(p), self.nodes[right].bounds.importance(p));
+            if il + ir <= 0.0 { return None; }
+
+            let p_left = il / (il + ir);
+            if u < p_left {
+                u = (u / p_left).min(1.0 - f64::EPSILON);
+                pmf *= p_left;
+                idx = left;
+            } else {
+                u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f64::EPSILON);
+                pmf *= 1.0 - p_left;
+                idx = right;
+            }
+        }
+    }
+
+    // probability that sample picks `light` for `p`
+    pub fn pmf(&self, p: &Point3, light: usize) -> f64 {
+        let Some(mut idx) = self.leaves[light] else { return 0.0; };
+        if self.nodes[0].bounds.importance(p) <= 0.0 { return 0.0; }
+
+        // up from the leaf, taking the odds at each parent
+        let mut pmf = 1.0;
+        while self.parents[idx] != usize::MAX {
+            let parent = self.parents[idx];
+            let (left, right) = (parent + 1, self.nodes[parent].offset);
+            let (il, ir) = (self.nodes[left].bounds.importance(p), self.nodes[right].bounds.importance(p));
+            let mine = if idx == left { il } else { ir };
+            if mine <= 0.0 { return 0.0; }
+            pmf *= mine / (il + ir);
+            idx = parent;
+        }
+        pmf
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], parent: usize) -> usize {
+        let bounds = lights[1..].iter().fold(lights[0].1, |b, (_, l)| LightBounds::union(&b, l));
+        let node_idx = self.nodes.len();
+        self.nodes.push(LightNode { bounds, offset: lights[0].0, leaf: true });
+        self.parents.push(parent);
+
+        if lights.len() == 1 {
+            self.leaves[lights[0].0] = Some(node_idx);
+            return node_idx;
+        }
+
+        let centroid_box = lights.iter().fold(Aabb::empty(), |b, (_, l)| {
+            let c = l.bounds.centroid();
+            Aabb::surrounding(&b, &Aabb::from_points(c, c))
+        });
+
+        // the cheapest of the binned split planes over all three axes
+        let mut best: Option<(f64, usize, usize)> = None; // (cost, axis, bin)
+        for axis in 0..3 {
+            let extent = *centroid_box.axis(axis);
+            if extent.size() <= 0.0 { continue; }
+
+            let mut bins: [Option<LightBounds>; BINS] = [None; BINS];
+            for (_, l) in lights.iter() {
+                let b = bin_index(l.bounds.centroid()[axis], &extent);
+                bins[b] = Some(bins[b].map_or(*l, |acc| LightBounds::union(&acc, l)));
+            }
+
+            for split in 1..BINS {
+                let side = |range: &[Option<LightBounds>]| range.iter().flatten().copied().reduce(|a, b| LightBounds::union(&a, &b));
+                let (Some(left), Some(right)) = (side(&bins[..split]), side(&bins[split..])) else { continue; };
+                let cost = left.cost(axis) + right.cost(axis);
+                if best.is_none_or(|(c, _, _)| cost < c) { best = Some((cost, axis, split)); }
+            }
+        }
+
+        let mid = match best {
+            Some((_, axis, split)) => {
+                let extent = *centroid_box.axis(axis);
+                lights.sort_by_key(|(_, l)| bin_index(l.bounds.centroid()[axis], &extent) >= split);
+                lights.partition_point(|(_, l)| bin_index(l.bounds.centroid()[axis], &extent) < split)
+            }
+            None => lights.len() / 2 // all in the same place
+        };
+
+        let (left, right) = lights.split_at_mut(mid);
+        self.build(left, node_idx);
+        let right_idx = self.build(right, node_idx);
+
+        let node = &mut self.nodes[node_idx];
+        node.offset = right_idx;
+        node.leaf = false;
+        node_idx
+    }
+}
+
+// cos and sin of max(0, a - b), from those of a and b
+#[inline(always)]
+fn cos_sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> (f64, f64) {
+    if cos_a > cos_b { return (1.0, 0.0); }
+    (cos_a * cos_b + sin_a * sin_b, sin_a * cos_b - cos_a * sin_b)
+}
+
+// the narrowest cone around two others, as an axis and cos of its spread
+fn cone_union(wa: &Vec3, cos_a: f64, wb: &Vec3, cos_b: f64) -> (Vec3, f64) {
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = dot(wa, wb).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a { return (*wa, cos_a); }
+    if (theta_d + theta_a).min(PI) <= theta_b { return (*wb, cos_b); }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    if theta_o >= PI { return (*wa, -1.0); }
+
+    // turn wa towards wb until the cone reaches round both
+    let k = cross(wa, wb);
+    if k.near_zero() { return (*wa, -1.0); }
+    let k = unit_vector(k);
+    let theta_r = theta_o - theta_a;
+    let w = theta_r.cos() * *wa + theta_r.sin() * cross(&k, wa);
+    (unit_vector(w), theta_o.cos())
+}
+
+#[inline(always)]
+fn bin_index(c: f64, extent: &Interval) -> usize {
+    (((c - extent.min()) / extent.size() * BINS as f64) as usize).min(BINS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::*;
+
+    // small lights scattered over a box, facing all sorts of ways, some
+    // one sided and some giving nothing
+    fn lights() -> Vec<LightBounds> {
+        let mut sampler = Sampler::Independent(IndependentSampler::seeded(3));
+        (0..40).map(|i| {
+            let (x, y, z) = (sampler.next_1d(), sampler.next_1d(), sampler.next_1d());
+            let c = point3(10.0 * x, 10.0 * y, 10.0 * z);
+            let phi = if i % 7 == 0 { 0.0 } else { 0.1 + 10.0 * sampler.next_1d() };
+            let (w, cos_theta_o) = if i % 3 == 0 { (vec3(0, 0, 1), -1.0) } else { (randvec_in_unit_sphere(&mut sampler), 1.0) };
+            LightBounds { bounds: Aabb::from_points(c - vec3(0.2, 0.2, 0.2), c + vec3(0.2, 0.2, 0.2)), phi, w, cos_theta_o, cos_theta_e: 0.0 }
+        }).collect()
+    }
+
+    // sample picks each light as often as pmf says, and says so itself. a
+    // node can matter to `p` while neither child does, so some picks come
+    // to nothing, as often as the pmfs fall short of 1
+    #[test]
+    fn sample_matches_pmf() {
+        let bounds = lights();
+        let bvh = LightBvh::new(&bounds);
+        let n = 200_000;
+
+        for p in [point3(5, 5, 5), point3(-3, 12, 4), point3(0.5, 0.5, 0.5), point3(9, 1, 20)] {
+            let pmfs: Vec<f64> = (0..bounds.len()).map(|i| bvh.pmf(&p, i)).collect();
+            let total: f64 = pmfs.iter().sum();
+            assert!(total > 0.9 && total <= 1.0 + 1.0e-9, "pmf sums to {total}");
+            for (i, b) in bounds.iter().enumerate() {
+                if b.phi <= 0.0 { assert_eq!(pmfs[i], 0.0); }
+            }
+
+            // stratified, so counts come out within a few of n·pmf
+            let (mut counts, mut missed) = (vec![0; bounds.len()], 0);
+            for k in 0..n {
+                let Some((i, pmf)) = bvh.sample(&p, (k as f64 + 0.5) / n as f64) else { missed += 1; continue; };
+                assert!((pmf - pmfs[i]).abs() <= 1.0e-12 * pmfs[i].max(1.0), "sample gave {pmf}, pmf {}", pmfs[i]);
+                counts[i] += 1;
+            }
+            for (i, count) in counts.iter().enumerate() {
+                let expected = n as f64 * pmfs[i];
+                assert!((*count as f64 - expected).abs() <= 2.0 + 1.0e-6 * expected, "light {i}: {count} against {expected}");
+            }
+            let expected = n as f64 * (1.0 - total);
+            assert!((missed as f64 - expected).abs() <= 2.0 * bounds.len() as f64, "missed {missed} against {expected}");
+        }
+    }
+
+    #[test]
+    fn empty_tree_samples_nothing() {
+        let bvh = LightBvh::new(&[]);
+        assert!(bvh.sample(&point3(0, 0, 0), 0.5).is_none());
+    }
+}
@@ -6,75 +6,75 @@ mod hittable;
 mod util;
 mod camera;
 mod material;
-use vec3::*;
-use hittable::*;
-use util::*;
-use std::{f64::INFINITY, rc::Rc};
-use camera::*;
-use rand::{thread_rng, Rng};
-use material::*;
-use std::sync::Arc;
-use tobj::{self, LoadOptions};
+mod bvh;
+mod mesh;
+mod texture;
+mod light;
+mod scene;
+mod integrator;
+mod bdpt;
+mod sppm;
+mod sampler;
+mod mlt;
+mod medium;
+mod voxel;
+mod background;
+mod ies;
+mod lightbvh;
+mod spectrum;
+mod ior;
+mod microfacet;
+mod scenes;
+use integrator::*;
+use texture::*;
+use scenes::*;
 
 fn main() {
-    let mut world = HittableList::new();
-    let ground_material = Arc::new(Material::Lambertian(Lambertian { albedo: colour(0.5, 0.5, 0.5) }));
+    let mut integrator = Integrator::Path(PathIntegrator { max_depth: 25, rr_depth: 3 });
+    let mut scene = String::from("suzanne");
+    let mut options = SceneOptions { environment: None, texture: None, wrap: WrapMode::Repeat, grid: None, grid_size: None, ies: None, spectral: false };
 
-    let mat = Arc::new(
-        Material::Metal(Metal{ albedo: colour(0.6, 0.6, 0.6), fuzz: 0.3 })
-    );
-    let mat1 = Arc::new(
-        Material::Lambertian(Lambertian{ albedo: colour(0.4, 0.4, 0.7) })
-    );
-    let mat2 = Arc::new(
-        Material::Dielectric(Dielectric{ mu: 1.5 })
-    );
-    let mat3 = Arc::new(Material::TestMaterial(TestMaterial{ albedo: colour(0,0,0) }));
-
-    let load_options = LoadOptions { single_index: false, triangulate: true, ignore_points: true, ignore_lines: true };
-
-    let scene = tobj::load_obj("suzanne.obj", &load_options);
-    assert!(scene.is_ok());
-    let (models, materials) = scene.expect("Failed to load OBJ file");
-
-
-    for (i, m) in models.iter().enumerate() {
-        let mesh = &m.mesh;
-        assert_eq!(mesh.indices.len() % 3, 0);
-        for v in 0..mesh.indices.len() / 3 {
-            let i1 = mesh.indices[3*v] as usize;
-            let i2 = mesh.indices[3*v + 1] as usize;
-            let i3 = mesh.indices[3*v + 2] as usize;
-
-            let v1 = point3(
-                mesh.positions[3*i1], mesh.positions[3*i1 + 1], mesh.positions[3*i1 + 2]
-            );
-            let v2 = point3(
-                mesh.positions[3*i2], mesh.positions[3*i2 + 1], mesh.positions[3*i2 + 2]
-            );
-            let v3 = point3(
-                mesh.positions[3*i3], mesh.positions[3*i3 + 1], mesh.positions[3*i3 + 2]
-            );
-
-            let triangle = Triangle::new(v1, v2, v3, mat.clone());
-            world.add(Arc::new(Hittable::Triangle(triangle)));
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&format!("{arg} needs a value")));
+        match arg.as_str() {
+            "--integrator" => {
+                let name = value();
+                integrator = Integrator::by_name(&name).unwrap_or_else(|| usage(&format!("unknown integrator '{name}'")));
+            }
+            "--scene" => scene = value(),
+            "--env" => options.environment = Some(value()),
+            "--texture" => options.texture = Some(value()),
+            "--wrap" => {
+                options.wrap = match value().as_str() {
+                    "repeat" => WrapMode::Repeat,
+                    "mirror" => WrapMode::Mirror,
+                    "clamp" => WrapMode::Clamp,
+                    other => usage(&format!("unknown wrap mode '{other}'"))
+                }
+            }
+            "--grid" => options.grid = Some(value()),
+            "--grid-size" => {
+                let size = value();
+                let dims: Vec<usize> = size.split('x').filter_map(|n| n.parse().ok()).collect();
+                let [nx, ny, nz] = dims[..] else { usage(&format!("grid size '{size}' isn't NXxNYxNZ")) };
+                options.grid_size = Some((nx, ny, nz));
+            }
+            "--ies" => options.ies = Some(value()),
+            "--spectral" => options.spectral = true,
+            _ => usage(&format!("unknown option '{arg}'"))
         }
     }
 
-    world.add(Arc::new(Hittable::Sphere(Sphere::new(point3(-2,0,2),1.0,mat.clone()))));
-    world.add(Arc::new(Hittable::Sphere(Sphere::new(point3(0,-102,0),100.0,ground_material))));
-
-    let mut cam = Camera::new(16.0/9.0, 500);
-    cam.sample_rate = 50;
-    cam.max_ray_bounces = 25;
-    cam.vfov = 20.0;
-    cam.look_from = point3(5.0, 0, 5.0);
-    cam.look_at = point3(0, 0, 0);
-    cam.vup = vec3(0, 1, 0);
-
-    cam.defocus_angle = 0.0;
-    cam.focus_distance = 2.0;
+    let (scene, cam) = build(&scene, &options).unwrap_or_else(|e| usage(&e));
+    cam.render(&scene, &integrator);
+}
 
-    cam = cam.reinit();
-    cam.render(&Hittable::HittableList(world));
+fn usage(error: &str) -> ! {
+    eprintln!("{error}");
+    eprintln!("usage: pbptr [--integrator naive|bsdf|path|bdpt|sppm|mlt|direct|ao|normals|depth]");
+    eprintln!("             [--scene suzanne|materials|textures|volumes|lights|sky] [--spectral] [--env FILE]");
+    eprintln!("             [--texture FILE] [--wrap repeat|mirror|clamp]");
+    eprintln!("             [--grid FILE] [--grid-size NXxNYxNZ] [--ies FILE]");
+    std::process::exit(2)
 }
@@ -1,29 +1,110 @@
 use crate::vec3::*;
 use crate::hittable::*;
 use crate::ray::*;
+use crate::texture::*;
+use crate::medium::*;
 use enum_dispatch::enum_dispatch;
-use rand::random;
+use crate::sampler::*;
+use crate::spectrum::*;
+use crate::ior::*;
+use crate::microfacet::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct Lambertian {
-    pub albedo: Colour
+    pub albedo: Arc<Texture>
 }
 
+// a conductor with ggx microfacets, reflecting by the fresnel equations
+// for its complex index of refraction, times a tint
 pub struct Metal {
-    pub albedo: Colour,
-    pub fuzz: f64
+    pub tint: Arc<Texture>, // white to leave the metal its own colour
+    pub eta: Colour,
+    pub k: Colour, // absorption, the imaginary part of the index
+    pub measured: Option<Conductor>, // the index against wavelength, in place of eta and k when tracing wavelengths
+    pub roughness: Arc<Texture>, // 0 for a mirror
+    pub anisotropy: Arc<Texture> // 0 for round highlights, up to 1 for ones drawn out along u
 }
 
 pub struct Dielectric {
-    pub mu: f64
+    pub mu: Ior,
+    pub roughness: Arc<Texture>, // 0 for clear glass, higher for frosted
+    pub medium: Option<Arc<Medium>> // filling the inside, for murky glass and liquids
 }
 
-pub struct TestMaterial {
-    pub albedo: Colour
+// the disney principled bsdf, as exported by blender and other content
+// tools: a diffuse base under a dielectric coat, blending into metal and
+// glass, with a sheen and a clear lacquer on top. everything but the base
+// colour runs from 0 to 1
+pub struct Principled {
+    pub base_colour: Arc<Texture>,
+    pub metallic: Arc<Texture>,
+    pub roughness: Arc<Texture>,
+    pub specular: Arc<Texture>, // 0.5 is 4% reflectance, glass of index 1.5
+    pub sheen: Arc<Texture>, // velvety glow at grazing angles, for cloth
+    pub sheen_tint: Arc<Texture>, // 0 for a white sheen, up to 1 for one in the base colour's hue
+    pub clearcoat: Arc<Texture>,
+    pub clearcoat_roughness: Arc<Texture>,
+    pub transmission: Arc<Texture>,
+    pub anisotropy: Arc<Texture> // 0 for round highlights, up to 1 for ones drawn out along u
+}
+
+// a principled surface's parameters at a hit, and how often each of its
+// lobes is sampled
+struct Lobes {
+    base: Colour,
+    roughness: f64,
+    sheen: Colour,
+    spec0: Colour, // reflectance facing the light
+    eta: f64, // for transmission, far side over near side
+    distrib: TrowbridgeReitz,
+    coat: TrowbridgeReitz,
+    weights: [f64; 4], // diffuse, specular, clearcoat, glass
+    probs: [f64; 4]
+}
+
+pub struct DiffuseLight {
+    pub emit: Arc<Texture>,
+    pub two_sided: bool // otherwise only the side the normal faces glows
+}
+
+// invisible surface marking where a medium starts, rays carry on through
+pub struct Interface {
+    pub medium: Option<Arc<Medium>> // on the inside
 }
 
 #[enum_dispatch]
 pub trait Scatter {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Colour)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray, Colour)>;
+
+    // bsdf times cosine for light arriving from `dir`, None for materials
+    // that only scatter in directions they choose themselves
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _dir: &Vec3, _lambda: &Wavelengths) -> Option<Colour> {
+        None
+    }
+
+    // solid angle density with which `scatter` picks `dir`
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _lambda: &Wavelengths) -> Colour {
+        colour(0, 0, 0)
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // medium filling the inside of surfaces made of this
+    fn medium(&self) -> Option<Arc<Medium>> {
+        None
+    }
+
+    // true for surfaces that only mark medium boundaries
+    fn is_interface(&self) -> bool {
+        false
+    }
 }
 
 #[enum_dispatch(Scatter)]
@@ -31,69 +112,588 @@ pub enum Material { // only albedo in each material? then just make a generic?
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
-    TestMaterial(TestMaterial)
+    Principled(Principled),
+    DiffuseLight(DiffuseLight),
+    Interface(Interface)
 }
 
 impl Scatter for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Colour)> {
-        let mut scatter_dir = rec.normal + randvec_in_unit_sphere();
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        let mut scatter_dir = rec.normal + randvec_in_unit_sphere(sampler);
 
         if scatter_dir.near_zero() {
             scatter_dir = rec.normal;
         }
 
-        Some((ray(rec.p, scatter_dir), self.albedo))
+        Some((rec.spawn_ray(scatter_dir), lambda.spectral(self.albedo.value(rec.u, rec.v, &rec.p))))
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, dir: &Vec3, lambda: &Wavelengths) -> Option<Colour> {
+        if dot(dir, &rec.geo_normal) <= 0.0 { return Some(colour(0, 0, 0)); }
+        let cosine = dot(&unit_vector(*dir), &rec.normal).max(0.0);
+        Some(lambda.spectral(self.albedo.value(rec.u, rec.v, &rec.p)) * (cosine / PI))
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> f64 {
+        // normal + unit vector is cosine distributed
+        dot(&unit_vector(*dir), &rec.normal).max(0.0) / PI
+    }
+}
+
+impl Metal {
+    pub fn gold(roughness: Arc<Texture>) -> Self {
+        Self {
+            tint: solid(colour(1, 1, 1)),
+            eta: colour(0.143119, 0.374957, 1.44248),
+            k: colour(3.98316, 2.38572, 1.60322),
+            measured: Some(Conductor::gold()),
+            roughness,
+            anisotropy: constant(0.0)
+        }
+    }
+
+    pub fn copper(roughness: Arc<Texture>) -> Self {
+        Self {
+            tint: solid(colour(1, 1, 1)),
+            eta: colour(0.200438, 0.924033, 1.10221),
+            k: colour(3.91295, 2.45285, 2.14219),
+            measured: Some(Conductor::copper()),
+            roughness,
+            anisotropy: constant(0.0)
+        }
+    }
+
+    pub fn aluminium(roughness: Arc<Texture>) -> Self {
+        Self {
+            tint: solid(colour(1, 1, 1)),
+            eta: colour(1.65746, 0.880369, 0.521229),
+            k: colour(9.22387, 6.26952, 4.837),
+            measured: Some(Conductor::aluminium()),
+            roughness,
+            anisotropy: constant(0.0)
+        }
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(self.roughness.scalar(rec.u, rec.v, &rec.p), self.anisotropy.scalar(rec.u, rec.v, &rec.p))
+    }
+
+    // an index is no colour, so without a measured one it's the rgb
+    // reflectance that gets turned into a spectrum
+    fn fresnel(&self, cos_i: f64, lambda: &Wavelengths) -> Colour {
+        match &self.measured {
+            Some(m) => fresnel_complex(cos_i, &lambda.sampled(self.eta, |l| m.at(l).0), &lambda.sampled(self.k, |l| m.at(l).1)),
+            None => lambda.spectral(fresnel_complex(cos_i, &self.eta, &self.k))
+        }
+    }
+
+    // the fresnel term times the tint at the hit
+    fn reflectance(&self, rec: &HitRecord, cos_i: f64, lambda: &Wavelengths) -> Colour {
+        lambda.spectral(self.tint.value(rec.u, rec.v, &rec.p)) * self.fresnel(cos_i, lambda)
     }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Colour)> {
-        let mut refl = reflect(&r_in.dir(), &rec.normal);
-        refl = unit_vector(refl) + (self.fuzz * randvec_in_unit_sphere()); 
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let distrib = self.distribution(rec);
+        if distrib.is_smooth() {
+            let wi = vec3(-wo.x(), -wo.y(), wo.z());
+            return Some((rec.spawn_ray(frame.to_world(&wi)), self.reflectance(rec, wo.z().abs(), lambda)));
+        }
 
-        let scatrd_ray = ray(rec.p, refl);
-        if dot(&scatrd_ray.dir(), &rec.normal) > 0.0 {
-            return Some((scatrd_ray, self.albedo));
-        } else {
-            return None;
+        // a microfacet facing the way the light leaves, then the mirror
+        // direction about it. f * cos / pdf comes down to F G / G1
+        let wm = distrib.sample_wm(&wo, sampler);
+        let wi = mirror(&wo, &wm);
+        let dir = frame.to_world(&wi);
+        if wi.z() * wo.z() <= 0.0 || dot(&dir, &rec.geo_normal) <= 0.0 { return None; }
+
+        let weight = self.reflectance(rec, dot(&wo, &wm).abs(), lambda) * (distrib.g(&wo, &wi) / distrib.g1(&wo));
+        Some((rec.spawn_ray(dir), weight))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3, lambda: &Wavelengths) -> Option<Colour> {
+        let distrib = self.distribution(rec);
+        if distrib.is_smooth() { return None; }
+        if dot(dir, &rec.geo_normal) <= 0.0 { return Some(colour(0, 0, 0)); }
+
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let wi = frame.to_local(&unit_vector(*dir));
+        let Some(wm) = half_vector(&wo, &wi) else { return Some(colour(0, 0, 0)); };
+
+        // torrance-sparrow, D F G / (4 cos_o cos_i), times cos_i
+        let f = distrib.d(&wm) * distrib.g(&wo, &wi) / (4.0 * wo.z().abs());
+        Some(self.reflectance(rec, dot(&wo, &wm).abs(), lambda) * f)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> f64 {
+        let distrib = self.distribution(rec);
+        if distrib.is_smooth() { return 0.0; }
+
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let wi = frame.to_local(&unit_vector(*dir));
+        let Some(wm) = half_vector(&wo, &wi) else { return 0.0; };
+        distrib.pdf(&wo, &wm) / (4.0 * dot(&wo, &wm).abs())
+    }
+}
+
+// whether shading space and the true normal agree on whether `wo` and `wi`,
+// or `r_in` and `dir` in world space, are on the same side of the surface.
+// `r_in` may come from behind it when a path is evaluated in reverse
+#[inline(always)]
+fn sides_agree(wo: &Vec3, wi: &Vec3, r_in: &Ray, dir: &Vec3, rec: &HitRecord) -> bool {
+    (wo.z() * wi.z() > 0.0) == (dot(&r_in.dir(), &rec.geo_normal) * dot(dir, &rec.geo_normal) < 0.0)
+}
+
+impl Dielectric {
+    // index at the path's wavelength, and what picking that wavelength does
+    // to its throughput. glass whose index varies splits light, so the
+    // path carries on as the one wavelength
+    fn index(&self, lambda: &mut Wavelengths) -> (f64, Colour) {
+        match self.mu {
+            Ior::Constant(mu) => (mu, colour(1, 1, 1)),
+            ior => { let (l, tint) = lambda.disperse(); (ior.at(l), tint) }
         }
     }
+
+    // index on the far side of the surface over the one `r_in` is in
+    fn eta(&self, mu: f64, rec: &HitRecord) -> f64 {
+        if rec.front_face { mu } else { 1.0 / mu }
+    }
+
+    // bsdf times cosine and the density scatter picks `dir` with, for
+    // rough glass
+    fn rough(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3, distrib: &TrowbridgeReitz, eta: f64) -> (f64, f64) {
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let wi = frame.to_local(&unit_vector(*dir));
+        if !sides_agree(&wo, &wi, r_in, dir, rec) { return (0.0, 0.0); }
+        rough_dielectric(&wo, &wi, distrib, eta)
+    }
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self,r_in: &Ray,rec: &HitRecord) -> Option<(Ray,Colour)> {
-        let ri = if rec.front_face { 1.0 / self.mu } else { self.mu };
-
+    fn scatter(&self,r_in: &Ray,rec: &HitRecord, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray,Colour)> {
+        let (mu, attenuation) = self.index(lambda);
+        let eta = self.eta(mu, rec);
         let uni_dir = unit_vector(r_in.dir());
-        let dotval = dot(&uni_dir, &rec.normal);
 
-        let cos_theta = if dotval < 1.0 { dotval } else { 1.0 };
-        let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
-        let mut ray_dir = refract(&uni_dir, &rec.normal, ri);
+        let distrib = TrowbridgeReitz::new(self.roughness.scalar(rec.u, rec.v, &rec.p), 0.0);
+        if distrib.is_smooth() {
+            let cos_theta = dot(&-uni_dir, &rec.normal).min(1.0);
+            let ray_dir = if fresnel_dielectric(cos_theta, eta) > sampler.next_1d() {
+                reflect(&uni_dir, &rec.normal)
+            } else {
+                refract(&uni_dir, &rec.normal, 1.0 / eta)
+            };
+            return Some((rec.spawn_ray(ray_dir), attenuation));
+        }
+
+        // f * cos / pdf comes down to G / G1, over eta when it refracts
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-uni_dir);
+        let wi = sample_rough_dielectric(&wo, &distrib, eta, sampler)?;
+        let dir = frame.to_world(&wi);
+        if !sides_agree(&wo, &wi, r_in, &dir, rec) { return None; }
+        let etap = if wo.z() * wi.z() > 0.0 { 1.0 } else if wo.z() > 0.0 { eta } else { 1.0 / eta };
+        Some((rec.spawn_ray(dir), attenuation * (distrib.g(&wo, &wi) / (distrib.g1(&wo) * etap))))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3, _lambda: &Wavelengths) -> Option<Colour> {
+        // dispersive glass only knows its index once scatter has picked a
+        // wavelength, so it's left to find lights by itself
+        let Ior::Constant(mu) = self.mu else { return None; };
+        let distrib = TrowbridgeReitz::new(self.roughness.scalar(rec.u, rec.v, &rec.p), 0.0);
+        if distrib.is_smooth() { return None; }
+
+        let (f, _) = self.rough(r_in, rec, dir, &distrib, self.eta(mu, rec));
+        Some(colour(f, f, f))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> f64 {
+        let Ior::Constant(mu) = self.mu else { return 0.0; };
+        let distrib = TrowbridgeReitz::new(self.roughness.scalar(rec.u, rec.v, &rec.p), 0.0);
+        if distrib.is_smooth() { return 0.0; }
+
+        self.rough(r_in, rec, dir, &distrib, self.eta(mu, rec)).1
+    }
+
+    fn medium(&self) -> Option<Arc<Medium>> {
+        self.medium.clone()
+    }
+}
+
+// principled surfaces are never perfectly smooth, so their lobes can all be
+// mixed by density
+const MIN_ROUGHNESS: f64 = 0.05;
+// an index of 1 is no surface at all, and nothing would get through glass
+// with it
+const MIN_IOR: f64 = 1.01;
+
+impl Principled {
+    // a plastic-like default, with the other parameters to set as needed
+    pub fn new(base_colour: Arc<Texture>) -> Self {
+        Self {
+            base_colour,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.1),
+            transmission: constant(0.0),
+            anisotropy: constant(0.0)
+        }
+    }
+
+    fn lobes(&self, rec: &HitRecord, lambda: &Wavelengths) -> Lobes {
+        let at = |t: &Arc<Texture>| t.scalar(rec.u, rec.v, &rec.p).clamp(0.0, 1.0);
+        let rgb = self.base_colour.value(rec.u, rec.v, &rec.p);
+        let base = lambda.spectral(rgb);
+        let (metallic, transmission) = (at(&self.metallic), at(&self.transmission));
+        let roughness = at(&self.roughness).max(MIN_ROUGHNESS);
+
+        // metals reflect their own colour, dielectrics a little white
+        let f0 = 0.08 * at(&self.specular);
+        let spec0 = (1.0 - metallic) * colour(f0, f0, f0) + metallic * base;
+        let ior = ((1.0 + f0.sqrt()) / (1.0 - f0.sqrt())).max(MIN_IOR);
+
+        let glass = (1.0 - metallic) * transmission;
+        let weights = [(1.0 - metallic) * (1.0 - transmission), 1.0 - glass, 0.25 * at(&self.clearcoat), glass];
+
+        // roughly in proportion to how much each lobe reflects, using the
+        // average of schlick's fresnel over the hemisphere. judged from the
+        // rgb colours, so the densities are the same at any wavelengths
+        let sheen = at(&self.sheen);
+        let hue = if rgb.luminance() > 0.0 { rgb / rgb.luminance() } else { colour(1, 1, 1) };
+        let sheen_tint = at(&self.sheen_tint);
+        let sheen_colour = sheen * ((1.0 - sheen_tint) * colour(1, 1, 1) + sheen_tint * hue);
+        let avg = |f0: f64| f0 + (1.0 - f0) / 21.0;
+        let probs = [
+            weights[0] * (rgb.luminance() + sheen),
+            weights[1] * avg((1.0 - metallic) * f0 + metallic * rgb.luminance()),
+            weights[2] * avg(0.04),
+            weights[3]
+        ];
+        let total: f64 = probs.iter().sum();
+        let probs = if total > 0.0 { probs.map(|p| p / total) } else { [0.0; 4] };
 
-        if ri * sin_theta > 1.0 || reflectance(cos_theta, self.mu) > random() {
-            ray_dir = reflect(&uni_dir, &rec.normal);
+        Lobes {
+            base,
+            roughness,
+            sheen: lambda.spectral(sheen_colour),
+            spec0,
+            eta: if rec.front_face { ior } else { 1.0 / ior },
+            distrib: TrowbridgeReitz::new(roughness, at(&self.anisotropy)),
+            coat: TrowbridgeReitz::new(at(&self.clearcoat_roughness).max(MIN_ROUGHNESS), 0.0),
+            weights,
+            probs
         }
+    }
+
+    // bsdf times cosine summed over the lobes, and the density of picking
+    // `wi` by sampling any of them
+    fn evaluate(&self, l: &Lobes, wo: &Vec3, wi: &Vec3) -> (Colour, f64) {
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        let mut f = colour(0, 0, 0);
+        let mut pdf = 0.0;
+
+        if let Some(wh) = half_vector(wo, wi) {
+            let cos_d = dot(wi, &wh).abs();
+            let fw = (1.0 - cos_d).powi(5);
+
+            // burley's diffuse, brighter at grazing angles when rough, plus
+            // sheen
+            let fd90 = 0.5 + 2.0 * l.roughness * cos_d * cos_d;
+            let fl = 1.0 + (fd90 - 1.0) * (1.0 - cos_i.abs()).powi(5);
+            let fv = 1.0 + (fd90 - 1.0) * (1.0 - cos_o.abs()).powi(5);
+            let diffuse = l.base * (fl * fv / PI) + l.sheen * fw;
+            f += l.weights[0] * cos_i.abs() * diffuse;
+            pdf += l.probs[0] * cos_i.abs() / PI;
+
+            let spec = l.distrib.d(&wh) * l.distrib.g(wo, wi) / (4.0 * cos_o.abs());
+            f += (l.weights[1] * spec) * (l.spec0 + (colour(1, 1, 1) - l.spec0) * fw);
+            pdf += l.probs[1] * l.distrib.pdf(wo, &wh) / (4.0 * dot(wo, &wh).abs());
 
-        Some((ray(rec.p, ray_dir), colour(1.0, 1.0, 1.0)))
+            let coat = l.coat.d(&wh) * l.coat.g(wo, wi) / (4.0 * cos_o.abs());
+            let c = l.weights[2] * coat * (0.04 + 0.96 * fw);
+            f += colour(c, c, c);
+            pdf += l.probs[2] * l.coat.pdf(wo, &wh) / (4.0 * dot(wo, &wh).abs());
+        }
+
+        // glass tints what it lets through by the base colour
+        if l.weights[3] > 0.0 {
+            let (fg, pg) = rough_dielectric(wo, wi, &l.distrib, l.eta);
+            let tint = if cos_o * cos_i < 0.0 { l.base } else { colour(1, 1, 1) };
+            f += (l.weights[3] * fg) * tint;
+            pdf += l.probs[3] * pg;
+        }
+
+        (f, pdf)
     }
 }
 
-impl Scatter for TestMaterial {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray,Colour)> {
-        let scatter_dir = rec.normal;
-        if rec.front_face {
-            return Some((ray(rec.p, scatter_dir), colour(1,1,1)));
+impl Scatter for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        let l = self.lobes(rec, lambda);
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        if wo.z() == 0.0 { return None; }
+
+        // one lobe picks the direction, and all of them weight it
+        let u = sampler.next_1d();
+        let wi = if u < l.probs[0] {
+            let d = randvec_in_unit_disc(sampler);
+            let z = (1.0 - d.x() * d.x() - d.y() * d.y()).max(0.0).sqrt();
+            vec3(d.x(), d.y(), z.copysign(wo.z()))
+        } else if u < l.probs[0] + l.probs[1] {
+            mirror(&wo, &l.distrib.sample_wm(&wo, sampler))
+        } else if u < l.probs[0] + l.probs[1] + l.probs[2] {
+            mirror(&wo, &l.coat.sample_wm(&wo, sampler))
+        } else if l.probs[3] > 0.0 {
+            sample_rough_dielectric(&wo, &l.distrib, l.eta, sampler)?
         } else {
-            return Some((ray(rec.p, scatter_dir), colour(0,0,0)));
-        }
+            return None;
+        };
+
+        let dir = frame.to_world(&wi);
+        if !sides_agree(&wo, &wi, r_in, &dir, rec) { return None; }
+        let (f, pdf) = self.evaluate(&l, &wo, &wi);
+        if pdf <= 0.0 { return None; }
+        Some((rec.spawn_ray(dir), f / pdf))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3, lambda: &Wavelengths) -> Option<Colour> {
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let wi = frame.to_local(&unit_vector(*dir));
+        if !sides_agree(&wo, &wi, r_in, dir, rec) { return Some(colour(0, 0, 0)); }
+        Some(self.evaluate(&self.lobes(rec, lambda), &wo, &wi).0)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, dir: &Vec3) -> f64 {
+        let frame = Frame::new(rec);
+        let wo = frame.to_local(&-unit_vector(r_in.dir()));
+        let wi = frame.to_local(&unit_vector(*dir));
+        if !sides_agree(&wo, &wi, r_in, dir, rec) { return 0.0; }
+        // the densities don't depend on the wavelengths
+        self.evaluate(&self.lobes(rec, &Wavelengths::RGB), &wo, &wi).1
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _lambda: &mut Wavelengths, _sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        None
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, lambda: &Wavelengths) -> Colour {
+        if !self.two_sided && !rec.front_face { return colour(0, 0, 0); }
+        lambda.spectral(self.emit.value(rec.u, rec.v, &rec.p))
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
     }
 }
 
-fn reflectance(cosine: f64, mu: f64) -> f64 {
-    // schlick's approximation
-    let mut r0 = (1.0 - mu) / (1.0 + mu);
-    r0 = r0*r0;
+impl Scatter for Interface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, _lambda: &mut Wavelengths, _sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        Some((rec.spawn_ray(r_in.dir()), colour(1, 1, 1)))
+    }
+
+    fn medium(&self) -> Option<Arc<Medium>> {
+        self.medium.clone()
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a hit at the origin of a triangle facing +z, along a ray arriving from
+    // unit vector `wo`, with u along x
+    fn hit(material: Material, wo: Vec3) -> (Ray, HitRecord) {
+        let r_in = ray(wo, -wo);
+        let mut rec = triangle_record(&r_in, 1.0, point3(-1, -1, 0), point3(1, -1, 0), point3(0, 1, 0), Arc::new(material));
+        rec.set_surface_coords(0.5, 0.5, vec3(1, 0, 0), vec3(0, 1, 0));
+        (r_in, rec)
+    }
 
-    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
-}
\ No newline at end of file
+    // sums eval and pdf over the sphere of directions, on a grid fine enough
+    // for the lobes tested here
+    fn integrate(r_in: &Ray, rec: &HitRecord) -> (Colour, f64) {
+        let (ntheta, nphi) = (800, 1200);
+        let (d_theta, d_phi) = (PI / ntheta as f64, 2.0 * PI / nphi as f64);
+        let (mut f, mut pdf) = (colour(0, 0, 0), 0.0);
+        for i in 0..ntheta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            let d_omega = theta.sin() * d_theta * d_phi;
+            for j in 0..nphi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let dir = vec3(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                f += d_omega * rec.material.eval(r_in, rec, &dir, &Wavelengths::RGB).unwrap();
+                pdf += d_omega * rec.material.pdf(r_in, rec, &dir);
+            }
+        }
+        (f, pdf)
+    }
+
+    // what scatter returns is eval over pdf for the direction it picked, it
+    // picks one as often as the pdf says it should, and its weights average
+    // out to the integral of eval
+    fn check(material: Material, wo: Vec3) {
+        let (r_in, rec) = hit(material, unit_vector(wo));
+        let mut sampler = Sampler::Independent(IndependentSampler::seeded(7));
+        let n = 200_000;
+
+        let mut lambda = Wavelengths::rgb(0.5);
+
+        let (mut scattered, mut mean) = (0, colour(0, 0, 0));
+        for _ in 0..n {
+            let Some((r, weight)) = rec.material.scatter(&r_in, &rec, &mut lambda, &mut sampler) else { continue; };
+            let f = rec.material.eval(&r_in, &rec, &r.dir(), &lambda).unwrap();
+            let pdf = rec.material.pdf(&r_in, &rec, &r.dir());
+            assert!(pdf > 0.0, "picked {} with a pdf of 0", r.dir());
+            assert!((f / pdf - weight).norm() <= 1.0e-9 * weight.norm().max(1.0), "{} against {}", f / pdf, weight);
+            scattered += 1;
+            mean += weight / n as f64;
+        }
+
+        let (f, pdf) = integrate(&r_in, &rec);
+        let picked = scattered as f64 / n as f64;
+        assert!(pdf <= 1.0 + 1.0e-3, "pdf integrates to {pdf}");
+        assert!((pdf - picked).abs() < 0.01, "pdf integrates to {pdf} but {picked} of samples scatter");
+        assert!((mean - f).norm() < 0.01 * f.norm().max(1.0), "mean weight {mean} but eval integrates to {f}");
+    }
+
+    fn rough_glass(mu: f64, roughness: f64) -> Material {
+        Material::Dielectric(Dielectric { mu: Ior::Constant(mu), roughness: constant(roughness), medium: None })
+    }
+
+    #[test]
+    fn rough_glass_sampling_matches_eval_and_pdf() {
+        for wo in [vec3(0, 0, 1), vec3(0.6, 0, 0.8), vec3(0.3, 0.4, -0.5), vec3(0.9, 0, 0.1)] {
+            check(rough_glass(1.5, 0.5), wo);
+            check(rough_glass(1.33, 0.7), wo);
+        }
+    }
+
+    // paths traced from lights evaluate glass the other way round, so it has
+    // to give the same answer with the directions swapped
+    #[test]
+    fn rough_glass_is_symmetric() {
+        for (wo, wi) in [(vec3(0, 0, 1), vec3(0.2, 0.1, -1)), (vec3(0.6, 0, 0.8), vec3(-0.5, 0.2, -0.6)), (vec3(0.3, 0, -1), vec3(0.4, 0.1, 1))] {
+            let (wo, wi) = (unit_vector(wo), unit_vector(wi));
+            let (r_in, rec) = hit(rough_glass(1.5, 0.5), wo);
+            let back = ray(rec.p + wi, -wi);
+
+            let lambda = Wavelengths::RGB;
+            let forward = rec.material.eval(&r_in, &rec, &wi, &lambda).unwrap() / wi.z().abs();
+            let reverse = rec.material.eval(&back, &rec, &wo, &lambda).unwrap() / wo.z().abs();
+            assert!(forward.x() > 0.0 && rec.material.pdf(&back, &rec, &wo) > 0.0);
+            assert!((forward - reverse).norm() < 1.0e-9 * forward.norm(), "{forward} against {reverse}");
+        }
+    }
+
+    #[test]
+    fn rough_metal_sampling_matches_eval_and_pdf() {
+        let mut brushed = Metal::copper(constant(0.4));
+        brushed.anisotropy = constant(0.7);
+        for wo in [vec3(0, 0, 1), vec3(0.6, 0, 0.8), vec3(0.2, 0.7, 0.3)] {
+            check(Material::Metal(Metal::gold(constant(0.3))), wo);
+            check(Material::Metal(Metal::aluminium(constant(0.8))), wo);
+        }
+        check(Material::Metal(brushed), vec3(0.5, 0.5, 0.7));
+    }
+
+    // the measured presets should look much like their rgb fits, as the
+    // film sees them at normal incidence
+    #[test]
+    fn measured_metals_match_their_rgb() {
+        let n = 10_000;
+        for metal in [Metal::gold(constant(0.0)), Metal::copper(constant(0.0)), Metal::aluminium(constant(0.0))] {
+            let rgb = metal.fresnel(1.0, &Wavelengths::RGB);
+            let mut spectral = colour(0, 0, 0);
+            for i in 0..n {
+                let lambda = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+                spectral += lambda.to_rgb(metal.fresnel(1.0, &lambda)) / n as f64;
+            }
+            let diff = spectral - rgb;
+            assert!(diff.x().abs().max(diff.y().abs()).max(diff.z().abs()) < 0.1, "{spectral} against {rgb}");
+        }
+    }
+
+    #[test]
+    fn tint_scales_metal_reflectance() {
+        let wo = unit_vector(vec3(0.3, 0.2, 1));
+        let dir = unit_vector(vec3(-0.2, -0.3, 1));
+        let tint = colour(0.2, 0.5, 0.8);
+        for roughness in [0.0, 0.3] {
+            let mut tinted = Metal::gold(constant(roughness));
+            tinted.tint = solid(tint);
+            let (r_in, plain) = hit(Material::Metal(Metal::gold(constant(roughness))), wo);
+            let (_, rec) = hit(Material::Metal(tinted), wo);
+
+            let mut lambda = Wavelengths::RGB;
+            let scatter = |rec: &HitRecord, lambda: &mut Wavelengths| {
+                rec.material.scatter(&r_in, rec, lambda, &mut Sampler::Independent(IndependentSampler::seeded(3))).unwrap()
+            };
+            let ((r, weight), (_, plain_weight)) = (scatter(&rec, &mut lambda), scatter(&plain, &mut lambda));
+            assert!((weight - tint * plain_weight).norm() < 1.0e-12, "{weight} against {plain_weight}");
+
+            if roughness > 0.0 {
+                for d in [r.dir(), dir] {
+                    let f = rec.material.eval(&r_in, &rec, &d, &lambda).unwrap();
+                    let plain_f = plain.material.eval(&r_in, &plain, &d, &lambda).unwrap();
+                    assert!((f - tint * plain_f).norm() < 1.0e-12, "{f} against {plain_f}");
+                }
+            }
+        }
+    }
+
+    fn principled(configure: fn(&mut Principled)) -> Material {
+        let mut p = Principled::new(solid(colour(0.8, 0.3, 0.2)));
+        configure(&mut p);
+        Material::Principled(p)
+    }
+
+    #[test]
+    fn principled_sampling_matches_eval_and_pdf() {
+        let configs: [fn(&mut Principled); 5] = [
+            |_| {},
+            |p| { p.metallic = constant(1.0); p.roughness = constant(0.3); p.anisotropy = constant(0.6); },
+            |p| { p.sheen = constant(1.0); p.sheen_tint = constant(1.0); p.roughness = constant(0.9); },
+            |p| { p.clearcoat = constant(1.0); p.clearcoat_roughness = constant(0.4); },
+            |p| { p.transmission = constant(1.0); p.roughness = constant(0.4); }
+        ];
+        for configure in configs {
+            for wo in [vec3(0, 0, 1), vec3(0.6, 0, 0.8), vec3(0.3, 0.4, -0.5)] {
+                check(principled(configure), wo);
+            }
+        }
+    }
+
+    // glass with no specular still has an index, so light gets through
+    #[test]
+    fn principled_glass_without_specular_transmits() {
+        let (r_in, rec) = hit(principled(|p| { p.transmission = constant(1.0); p.specular = constant(0.0); }), vec3(0.3, 0, 1));
+        let mut sampler = Sampler::Independent(IndependentSampler::seeded(3));
+        let mut lambda = Wavelengths::rgb(0.5);
+        let n = 10_000;
+        let mut through = colour(0, 0, 0);
+        for _ in 0..n {
+            let Some((r, weight)) = rec.material.scatter(&r_in, &rec, &mut lambda, &mut sampler) else { continue; };
+            if r.dir().z() < 0.0 { through += weight / n as f64; }
+        }
+        assert!(through.luminance() > 0.2, "only {through} gets through");
+    }
+}
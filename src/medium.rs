@@ -0,0 +1,395 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::sampler::*;
+use crate::voxel::*;
+use crate::spectrum::*;
+use enum_dispatch::enum_dispatch;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// the same density everywhere. coefficients are per unit distance
+pub struct HomogeneousMedium {
+    pub sigma_a: Colour, // absorption
+    pub sigma_s: Colour, // scattering
+    phase: PhaseFunction
+}
+
+// density read from a voxel grid stretched over `bounds`, e.g. clouds and
+// smoke. extinction is the same in every colour channel, so free flight can
+// be tracked exactly against a majorant
+pub struct GridMedium {
+    grid: VoxelGrid,
+    majorants: MajorantGrid,
+    bounds: Aabb,
+    pub sigma_t: f64, // extinction where the grid reads 1
+    pub albedo: Colour, // share of extinction that scatters
+    phase: PhaseFunction
+}
+
+// the Henyey-Greenstein phase function
+pub struct HenyeyGreenstein {
+    pub g: f64 // mean cosine, from -1 (all back) to 1 (all forward)
+}
+
+// fills a closed shape with a medium. the shape's surface only marks where
+// the medium starts and ends, rays go through it unchanged
+pub struct ConstantMedium {
+    boundary: Arc<Hittable>,
+    interface: Arc<Material>
+}
+
+// the bounding box of a grid medium, whose faces mark where it starts and
+// ends
+pub struct GridVolume {
+    bbox: Aabb,
+    interface: Arc<Material>
+}
+
+// somewhere light scatters, on a surface or inside a medium where there's
+// no surface to orient by
+#[derive(Clone, Copy)]
+pub enum Interaction<'a> {
+    Surface(&'a HitRecord),
+    Medium(Point3, &'a PhaseFunction)
+}
+
+// where free flight through a medium ended
+pub struct MediumSample {
+    pub t: Option<f64>, // a scattering point before t_max, or None if it got through
+    pub weight: Colour // transmittance and scattering over the density of the outcome
+}
+
+#[enum_dispatch]
+pub trait Attenuate {
+    // fraction of light getting from r.at(0) to r.at(t_max)
+    fn transmittance(&self, r: &Ray, t_max: f64, lambda: &Wavelengths, sampler: &mut Sampler) -> Colour;
+
+    // picks how far along `r` the next scattering happens
+    fn sample(&self, r: &Ray, t_max: f64, lambda: &Wavelengths, sampler: &mut Sampler) -> MediumSample;
+
+    // how the points it scatters at redirect light
+    fn phase(&self) -> &PhaseFunction;
+}
+
+#[enum_dispatch(Attenuate)]
+pub enum Medium {
+    Homogeneous(HomogeneousMedium),
+    Grid(GridMedium)
+}
+
+// how much of the light a medium scatters goes off in each direction,
+// which only depends on the angle it turns through
+#[enum_dispatch]
+pub trait Deflect {
+    // density of light travelling along unit vector `dir` carrying on along
+    // unit vector `new_dir`, per unit solid angle
+    fn p(&self, dir: &Vec3, new_dir: &Vec3) -> f64;
+
+    // a unit direction to carry on along, and p over the density it was
+    // picked with
+    fn sample_p(&self, dir: &Vec3, sampler: &mut Sampler) -> (Vec3, f64);
+}
+
+#[enum_dispatch(Deflect)]
+pub enum PhaseFunction {
+    HenyeyGreenstein(HenyeyGreenstein)
+}
+
+impl Attenuate for HomogeneousMedium {
+    fn transmittance(&self, r: &Ray, t_max: f64, lambda: &Wavelengths, _sampler: &mut Sampler) -> Colour {
+        beer(lambda.spectral(self.sigma_a) + lambda.spectral(self.sigma_s), t_max * r.dir().norm())
+    }
+
+    fn sample(&self, r: &Ray, t_max: f64, lambda: &Wavelengths, sampler: &mut Sampler) -> MediumSample {
+        let sigma_t = lambda.spectral(self.sigma_a) + lambda.spectral(self.sigma_s);
+        let speed = r.dir().norm();
+        let dist_max = t_max * speed;
+
+        // nothing to scatter off, so it only dims what gets through
+        if self.sigma_s.max_component() <= 0.0 { return MediumSample { t: None, weight: beer(sigma_t, dist_max) }; }
+
+        // distances follow one colour channel's falloff, picked at random,
+        // and are weighted by the density averaged over all three
+        let (u1, u2) = sampler.next_2d();
+        let channel = ((u1 * 3.0) as usize).min(2);
+        let dist = if sigma_t[channel] > 0.0 { -(1.0 - u2).ln() / sigma_t[channel] } else { f64::INFINITY };
+
+        if dist < dist_max {
+            let tr = beer(sigma_t, dist);
+            let pdf = average(tr * sigma_t);
+            if pdf <= 0.0 { return MediumSample { t: None, weight: colour(0, 0, 0) }; }
+            MediumSample { t: Some(dist / speed), weight: tr * lambda.spectral(self.sigma_s) / pdf }
+        } else {
+            let tr = beer(sigma_t, dist_max);
+            let pdf = average(tr);
+            if pdf <= 0.0 { return MediumSample { t: None, weight: colour(0, 0, 0) }; }
+            MediumSample { t: None, weight: tr / pdf }
+        }
+    }
+
+    fn phase(&self) -> &PhaseFunction {
+        &self.phase
+    }
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Colour, sigma_s: Colour, g: f64) -> Self {
+        Self { sigma_a, sigma_s, phase: HenyeyGreenstein { g }.into() }
+    }
+
+    // a clear medium that only absorbs, e.g. tinted glass, leaving `tint`
+    // of the light after `distance`
+    pub fn absorbing(tint: Colour, distance: f64) -> Self {
+        let f = |c: f64| -c.clamp(1.0e-6, 1.0).ln() / distance;
+        Self::new(colour(f(tint.x()), f(tint.y()), f(tint.z())), colour(0, 0, 0), 0.0)
+    }
+}
+
+impl Attenuate for GridMedium {
+    // ratio tracking (Novak et al. 2014)
+    fn transmittance(&self, r: &Ray, t_max: f64, _lambda: &Wavelengths, sampler: &mut Sampler) -> Colour {
+        let Some(span) = self.bounds.hit(r, interval(0.0, t_max)) else { return colour(1, 1, 1); };
+        let speed = r.dir().norm();
+        let mut tr = 1.0;
+
+        self.majorants.march(&self.local(&r.ori()), &self.local_dir(&r.dir()), span.min(), span.max(), |t0, t1, maj| {
+            let mu = maj * self.sigma_t * speed;
+            if mu <= 0.0 { return true; }
+
+            let mut t = t0;
+            loop {
+                t -= (1.0 - sampler.next_1d()).ln() / mu;
+                if t >= t1 { return true; }
+                tr *= 1.0 - self.density(&r.at(t)) * speed / mu;
+
+                // once little gets through, stop early at random
+                if tr < 0.1 {
+                    if sampler.next_1d() < 0.5 { tr = 0.0; return false; }
+                    tr *= 2.0;
+                }
+            }
+        });
+
+        colour(tr, tr, tr)
+    }
+
+    // delta tracking (Woodcock et al. 1965): collisions are proposed against
+    // the majorant, and the ones the real density doesn't account for are
+    // passed through
+    fn sample(&self, r: &Ray, t_max: f64, lambda: &Wavelengths, sampler: &mut Sampler) -> MediumSample {
+        let Some(span) = self.bounds.hit(r, interval(0.0, t_max)) else {
+            return MediumSample { t: None, weight: colour(1, 1, 1) };
+        };
+        let speed = r.dir().norm();
+        let mut scattered = None;
+
+        self.majorants.march(&self.local(&r.ori()), &self.local_dir(&r.dir()), span.min(), span.max(), |t0, t1, maj| {
+            let mu = maj * self.sigma_t * speed;
+            if mu <= 0.0 { return true; }
+
+            let mut t = t0;
+            loop {
+                t -= (1.0 - sampler.next_1d()).ln() / mu;
+                if t >= t1 { return true; }
+                if sampler.next_1d() * mu < self.density(&r.at(t)) * speed {
+                    scattered = Some(t);
+                    return false;
+                }
+            }
+        });
+
+        match scattered {
+            Some(t) => MediumSample { t: Some(t), weight: lambda.spectral(self.albedo) },
+            None => MediumSample { t: None, weight: colour(1, 1, 1) }
+        }
+    }
+
+    fn phase(&self) -> &PhaseFunction {
+        &self.phase
+    }
+}
+
+impl GridMedium {
+    // majorant_res cells a side is enough for the majorants to skip most
+    // empty space, 16 is a fair start
+    pub fn new(grid: VoxelGrid, bounds: Aabb, sigma_t: f64, albedo: Colour, g: f64, majorant_res: usize) -> Self {
+        let majorants = MajorantGrid::new(&grid, majorant_res);
+        Self { grid, majorants, bounds, sigma_t, albedo, phase: HenyeyGreenstein { g }.into() }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    // extinction per unit distance at world space point `p`
+    #[inline(always)]
+    fn density(&self, p: &Point3) -> f64 {
+        self.sigma_t * self.grid.lookup(&self.local(p))
+    }
+
+    // world space to the unit cube the grid fills
+    #[inline(always)]
+    fn local(&self, p: &Point3) -> Point3 {
+        let a = |n: usize| (p[n] - self.bounds.axis(n).min()) / self.bounds.axis(n).size();
+        point3(a(0), a(1), a(2))
+    }
+
+    #[inline(always)]
+    fn local_dir(&self, d: &Vec3) -> Vec3 {
+        vec3(d.x() / self.bounds.axis(0).size(), d.y() / self.bounds.axis(1).size(), d.z() / self.bounds.axis(2).size())
+    }
+}
+
+impl Deflect for HenyeyGreenstein {
+    fn p(&self, dir: &Vec3, new_dir: &Vec3) -> f64 {
+        hg_phase(dot(dir, new_dir), self.g)
+    }
+
+    fn sample_p(&self, dir: &Vec3, sampler: &mut Sampler) -> (Vec3, f64) {
+        let (u1, u2) = sampler.next_2d();
+        let g = self.g;
+        let cos_theta = if g.abs() < 1.0e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let sq = (1.0 - g*g) / (1.0 - g + 2.0 * g * u1);
+            ((1.0 + g*g - sq*sq) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        // angles are measured from the direction of travel
+        let (s, t) = coordinate_system(dir);
+        let new_dir = sin_theta * phi.cos() * s + sin_theta * phi.sin() * t + cos_theta * *dir;
+
+        // sampled exactly, so the weight is one
+        (new_dir, 1.0)
+    }
+}
+
+impl Interaction<'_> {
+    pub fn p(&self) -> Point3 {
+        match self {
+            Interaction::Surface(rec) => rec.p,
+            Interaction::Medium(p, _) => *p
+        }
+    }
+
+    // the way the path carries on from arriving along `r_in`, and its weight
+    pub fn scatter(&self, r_in: &Ray, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Option<(Ray, Colour)> {
+        match self {
+            Interaction::Surface(rec) => rec.material.scatter(r_in, rec, lambda, sampler),
+            Interaction::Medium(p, phase) => {
+                let (dir, weight) = phase.sample_p(&unit_vector(r_in.dir()), sampler);
+                Some((ray(*p, dir), colour(weight, weight, weight)))
+            }
+        }
+    }
+
+    // bsdf times cosine on surfaces, the phase function in media. None
+    // where only scatter can pick the way out
+    pub fn eval(&self, r_in: &Ray, dir: &Vec3, lambda: &Wavelengths) -> Option<Colour> {
+        match self {
+            Interaction::Surface(rec) => rec.material.eval(r_in, rec, dir, lambda),
+            Interaction::Medium(_, phase) => {
+                let p = phase.p(&unit_vector(r_in.dir()), &unit_vector(*dir));
+                Some(colour(p, p, p))
+            }
+        }
+    }
+
+    // solid angle density with which scatter picks `dir`
+    pub fn pdf(&self, r_in: &Ray, dir: &Vec3) -> f64 {
+        match self {
+            Interaction::Surface(rec) => rec.material.pdf(r_in, rec, dir),
+            Interaction::Medium(_, phase) => phase.p(&unit_vector(r_in.dir()), &unit_vector(*dir))
+        }
+    }
+
+    // a ray leaving along `dir`, off the surface if there is one
+    pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+        match self {
+            Interaction::Surface(rec) => rec.spawn_ray(dir),
+            Interaction::Medium(p, _) => ray(*p, dir)
+        }
+    }
+}
+
+impl Hit for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut rec = self.boundary.hit(r, ray_t)?;
+        rec.material = self.interface.clone();
+        Some(rec)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.boundary.aabb()
+    }
+}
+
+impl ConstantMedium {
+    // `boundary` should be closed, or rays can get in without getting out
+    pub fn new(boundary: Arc<Hittable>, medium: Arc<Medium>) -> Self {
+        Self { boundary, interface: Arc::new(Material::Interface(Interface { medium: Some(medium) })) }
+    }
+}
+
+impl Hit for GridVolume {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let span = self.bbox.hit(r, ray_t)?;
+
+        // where the ray gets in, or out if it starts inside
+        let t = if span.min() > ray_t.min() { span.min() } else if span.max() < ray_t.max() { span.max() } else { return None; };
+        let p = r.at(t);
+
+        // the face whose plane the point is closest to
+        let (mut axis, mut dist, mut sign) = (0, f64::INFINITY, 1.0);
+        for a in 0..3 {
+            let ax = self.bbox.axis(a);
+            for (d, s) in [((p[a] - ax.min()).abs(), -1.0), ((p[a] - ax.max()).abs(), 1.0)] {
+                if d < dist { (axis, dist, sign) = (a, d, s); }
+            }
+        }
+        let mut n = vec3(0, 0, 0);
+        n[axis] = sign;
+
+        let mut rec = HitRecord::new(r, n, p, t, self.interface.clone());
+        rec.set_outward_normal();
+        Some(rec)
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+impl GridVolume {
+    pub fn new(medium: GridMedium) -> Self {
+        let bbox = medium.bounds();
+        let medium = Arc::new(Medium::Grid(medium));
+        Self { bbox, interface: Arc::new(Material::Interface(Interface { medium: Some(medium) })) }
+    }
+}
+
+// density of scattering from travelling along one direction into another
+// at an angle with cosine `cos_theta`, per unit solid angle
+#[inline(always)]
+pub fn hg_phase(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g*g - 2.0 * g * cos_theta;
+    (1.0 - g*g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+// beer-lambert falloff over `dist`, taking channels without any density to
+// let everything through even over an infinite distance
+#[inline(always)]
+pub fn beer(sigma_t: Colour, dist: f64) -> Colour {
+    let f = |s: f64| if s > 0.0 { (-s * dist).exp() } else { 1.0 };
+    colour(f(sigma_t.x()), f(sigma_t.y()), f(sigma_t.z()))
+}
+
+#[inline(always)]
+fn average(c: Colour) -> f64 {
+    (c.x() + c.y() + c.z()) / 3.0
+}
@@ -0,0 +1,129 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::bvh::*;
+use std::sync::Arc;
+
+// vertex indices of one face, into the mesh's shared buffers
+#[derive(Clone, Copy)]
+struct Face {
+    p: [u32; 3],
+    n: Option<[u32; 3]>,
+    uv: Option<[u32; 3]>
+}
+
+pub struct Mesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<(f64, f64)>,
+    faces: Vec<Face>, // in the order the tree's leaves refer to
+    tree: BvhTree,
+    material: Arc<Material>
+}
+
+impl Mesh {
+    // takes a triangulated tobj mesh, loaded with `single_index: false`
+    pub fn new(mesh: &tobj::Mesh, material: Arc<Material>) -> Self {
+        assert_eq!(mesh.indices.len() % 3, 0);
+
+        let positions: Vec<Point3> = mesh.positions.chunks_exact(3)
+            .map(|p| point3(p[0], p[1], p[2])).collect();
+        let mut normals: Vec<Vec3> = mesh.normals.chunks_exact(3)
+            .map(|n| vec3(n[0], n[1], n[2])).collect();
+        let texcoords: Vec<(f64, f64)> = mesh.texcoords.chunks_exact(2)
+            .map(|t| (t[0], t[1])).collect();
+
+        let face_count = mesh.indices.len() / 3;
+        let corners = |idx: &Vec<u32>, f: usize| -> Option<[u32; 3]> {
+            if idx.len() != mesh.indices.len() { return None; }
+            Some([idx[3*f], idx[3*f + 1], idx[3*f + 2]])
+        };
+        let mut faces: Vec<Face> = (0..face_count).map(|f| Face {
+            p: corners(&mesh.indices, f).unwrap(),
+            n: corners(&mesh.normal_indices, f),
+            uv: corners(&mesh.texcoord_indices, f)
+        }).collect();
+
+        if normals.is_empty() || faces.iter().any(|f| f.n.is_none()) {
+            normals = vertex_normals(&positions, &faces);
+            for f in faces.iter_mut() { f.n = Some(f.p); }
+        }
+
+        let bounds: Vec<Aabb> = faces.iter().map(|f| {
+            let [a, b, c] = f.p.map(|i| positions[i as usize]);
+            Aabb::surrounding(&Aabb::from_points(a, b), &Aabb::from_points(c, c))
+        }).collect();
+        let (tree, order) = BvhTree::new(&bounds);
+        let faces = order.into_iter().map(|i| faces[i]).collect();
+
+        Self { positions, normals, texcoords, faces, tree, material }
+    }
+
+    #[inline(always)]
+    fn vertices(&self, f: &Face) -> (Point3, Point3, Point3) {
+        let [a, b, c] = f.p;
+        (self.positions[a as usize], self.positions[b as usize], self.positions[c as usize])
+    }
+
+    #[inline(always)]
+    fn uvs(&self, f: &Face) -> [(f64, f64); 3] {
+        match f.uv {
+            Some([a, b, c]) => [self.texcoords[a as usize], self.texcoords[b as usize], self.texcoords[c as usize]],
+            None => DEFAULT_TRIANGLE_UVS
+        }
+    }
+}
+
+impl Hit for Mesh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.tree.hit(r, ray_t, |i, t| {
+            let face = &self.faces[i];
+            let (v1, v2, v3) = self.vertices(face);
+            let (t, u, v) = intersect_triangle(r, t, v1, v2, v3)?;
+            let mut rec = triangle_record(r, t, v1, v2, v3, self.material.clone());
+
+            let uvs = self.uvs(face);
+            let (su, sv, dpdu, dpdv) = triangle_surface_coords([v1, v2, v3], uvs, u, v);
+            rec.set_surface_coords(su, sv, dpdu, dpdv);
+
+            if let Some([a, b, c]) = face.n {
+                let n = (1.0 - u - v) * self.normals[a as usize]
+                    + u * self.normals[b as usize]
+                    + v * self.normals[c as usize];
+                if !n.near_zero() { rec.set_shading_normal(n); }
+            }
+
+            Some(rec)
+        })
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.tree.bbox()
+    }
+
+    // every face of a glowing mesh is sampled as its own light
+    fn collect_emitters(&self, out: &mut Vec<Arc<Hittable>>) {
+        if !self.material.is_emissive() { return; }
+
+        for face in &self.faces {
+            let (v1, v2, v3) = self.vertices(face);
+            let triangle = Triangle::new(v1, v2, v3, self.material.clone()).with_uvs(self.uvs(face));
+            out.push(Arc::new(Hittable::Triangle(triangle)));
+        }
+    }
+}
+
+// area weighted, since the unnormalised cross product of two edges is
+// twice the face's area
+fn vertex_normals(positions: &[Point3], faces: &[Face]) -> Vec<Vec3> {
+    let mut normals = vec![vec3(0, 0, 0); positions.len()];
+    for f in faces {
+        let [a, b, c] = f.p.map(|i| positions[i as usize]);
+        let n = cross(&(b - a), &(c - a));
+        for i in f.p { normals[i as usize] += n; }
+    }
+
+    normals.into_iter().map(|n| if n.near_zero() { n } else { unit_vector(n) }).collect()
+}
@@ -0,0 +1,219 @@
+use crate::vec3::*;
+use crate::hittable::*;
+use crate::sampler::*;
+use std::f64::consts::PI;
+
+// below this a microfacet surface is treated as perfectly smooth
+const MIN_ALPHA: f64 = 1.0e-3;
+
+// shading space at a hit, with the shading normal as z and x along dpdu,
+// so anisotropic surfaces stretch their highlights along the texture's u
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3
+}
+
+// the trowbridge-reitz (ggx) distribution of microfacet normals, in shading
+// space. alpha_x and alpha_y are the roughness along x and y
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64
+}
+
+impl Frame {
+    pub fn new(rec: &HitRecord) -> Self {
+        let n = rec.normal;
+        let s = rec.dpdu - dot(&rec.dpdu, &n) * n;
+        let (s, t) = if s.near_zero() { coordinate_system(&n) } else { let s = unit_vector(s); (s, cross(&n, &s)) };
+        Self { s, t, n }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        vec3(dot(v, &self.s), dot(v, &self.t), dot(v, &self.n))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x() * self.s + v.y() * self.t + v.z() * self.n
+    }
+}
+
+impl TrowbridgeReitz {
+    // from roughness as artists set it, squared so it looks linear, and
+    // anisotropy from 0 for round highlights to 1 for ones drawn out along x
+    pub fn new(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Self { alpha_x: alpha / aspect, alpha_y: alpha * aspect }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < MIN_ALPHA
+    }
+
+    // density of microfacet normal `wm` per unit of projected area
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let (x, y) = (wm.x() / self.alpha_x, wm.y() / self.alpha_y);
+        let e = x * x + y * y + wm.z() * wm.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // smith's auxiliary function, for how much of the surface seen from `w`
+    // is hidden by other microfacets
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let z2 = w.z() * w.z();
+        if z2 <= 0.0 { return f64::INFINITY; }
+        let alpha2_tan2 = ((w.x() * self.alpha_x).powi(2) + (w.y() * self.alpha_y).powi(2)) / z2;
+        0.5 * ((1.0 + alpha2_tan2).sqrt() - 1.0)
+    }
+
+    // fraction of microfacets visible from `w`
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // fraction visible from both `wo` and `wi`, height correlated
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // density of the microfacet normals seen from `w`, which is what
+    // sample_wm picks from
+    pub fn pdf(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z() == 0.0 { return 0.0; }
+        self.g1(w) / w.z().abs() * self.d(wm) * dot(w, wm).abs()
+    }
+
+    // a microfacet normal visible from `w` (heitz 2018)
+    pub fn sample_wm(&self, w: &Vec3, sampler: &mut Sampler) -> Vec3 {
+        // to the hemisphere configuration, where the distribution is round
+        let mut wh = unit_vector(vec3(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()));
+        if wh.z() < 0.0 { wh = -wh; }
+        let t1 = if wh.z() < 0.99999 { unit_vector(cross(&vec3(0, 0, 1), &wh)) } else { vec3(1, 0, 0) };
+        let t2 = cross(&wh, &t1);
+
+        // a point on the disc the visible half of the hemisphere projects to
+        let p = randvec_in_unit_disc(sampler);
+        let h = (1.0 - p.x() * p.x()).sqrt();
+        let s = 0.5 * (1.0 + wh.z());
+        let (px, py) = (p.x(), (1.0 - s) * h + s * p.y());
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = px * t1 + py * t2 + pz * wh;
+
+        unit_vector(vec3(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1.0e-6)))
+    }
+}
+
+// the microfacet normal reflecting `wo` into `wi`, facing out of the
+// surface, if they're on the same side of it
+pub fn half_vector(wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+    if wo.z() * wi.z() <= 0.0 { return None; }
+    let wm = *wo + *wi;
+    if wm.near_zero() { return None; }
+    let wm = unit_vector(wm);
+    Some(if wm.z() < 0.0 { -wm } else { wm })
+}
+
+// bsdf times cosine, and the density sample_rough_dielectric picks `wi`
+// with, for glass with microfacets (walter et al. 2007). eta is the index
+// on the -z side over the index on the +z side
+pub fn rough_dielectric(wo: &Vec3, wi: &Vec3, distrib: &TrowbridgeReitz, eta: f64) -> (f64, f64) {
+    let (cos_o, cos_i) = (wo.z(), wi.z());
+    if cos_o == 0.0 || cos_i == 0.0 { return (0.0, 0.0); }
+    let reflect = cos_o * cos_i > 0.0;
+
+    // the microfacet normal that bends `wo` into `wi`
+    let etap = if reflect { 1.0 } else if cos_o > 0.0 { eta } else { 1.0 / eta };
+    let wm = etap * *wi + *wo;
+    if wm.near_zero() { return (0.0, 0.0); }
+    let wm = unit_vector(wm);
+    let wm = if wm.z() < 0.0 { -wm } else { wm };
+    if dot(&wm, wi) * cos_i < 0.0 || dot(&wm, wo) * cos_o < 0.0 { return (0.0, 0.0); }
+
+    let r = fresnel_dielectric(dot(wo, &wm), eta);
+    let dg = distrib.d(&wm) * distrib.g(wo, wi);
+    if reflect {
+        (r * dg / (4.0 * cos_o.abs()), r * distrib.pdf(wo, &wm) / (4.0 * dot(wo, &wm).abs()))
+    } else {
+        // radiance would be scaled by eta^2 going in, which is undone coming
+        // back out of closed objects. scaling by eta instead keeps the bsdf
+        // the same both ways, so paths traced from lights agree with ones
+        // traced from the camera
+        let denom = (dot(wi, &wm) + dot(wo, &wm) / etap).powi(2);
+        let dwm_dwi = dot(wi, &wm).abs() / denom;
+        ((1.0 - r) * dg * dot(wo, &wm).abs() * dwm_dwi / (cos_o.abs() * etap), (1.0 - r) * distrib.pdf(wo, &wm) * dwm_dwi)
+    }
+}
+
+// reflects off or refracts through a microfacet visible from `wo`, in
+// proportion to its fresnel term
+pub fn sample_rough_dielectric(wo: &Vec3, distrib: &TrowbridgeReitz, eta: f64, sampler: &mut Sampler) -> Option<Vec3> {
+    if wo.z() == 0.0 { return None; }
+    let wm = distrib.sample_wm(wo, sampler);
+    if fresnel_dielectric(dot(wo, &wm), eta) > sampler.next_1d() {
+        let wi = mirror(wo, &wm);
+        if wi.z() * wo.z() <= 0.0 { return None; }
+        Some(wi)
+    } else {
+        let wi = refracted(wo, &wm, eta)?;
+        if wi.z() * wo.z() >= 0.0 { return None; }
+        Some(wi)
+    }
+}
+
+// `wo` mirrored about `n`, both pointing away from the surface
+#[inline(always)]
+pub fn mirror(wo: &Vec3, n: &Vec3) -> Vec3 {
+    2.0 * dot(wo, n) * *n - *wo
+}
+
+// `wo` refracted through a surface with normal `n`, both pointing away from
+// it, where eta is the index on the far side over the index on `wo`'s.
+// None on total internal reflection
+pub fn refracted(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let (n, eta, cos_i) = match dot(wo, n) {
+        c if c < 0.0 => (-*n, 1.0 / eta, -c),
+        c => (*n, eta, c)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 { return None; }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + (cos_i / eta - cos_t) * n)
+}
+
+// fraction of light a dielectric reflects, with cos_i the cosine on the
+// side the light arrives from and eta the index on the far side over the
+// index on this one. light from inside (cos_i < 0) sees the inverse
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 { return 1.0; } // total internal reflection
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
+
+// fraction of light a conductor with complex index eta + ik reflects, per
+// colour channel, from air
+pub fn fresnel_complex(cos_i: f64, eta: &Colour, k: &Colour) -> Colour {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    colour(channel(eta.x(), k.x()), channel(eta.y(), k.y()), channel(eta.z(), k.z()))
+}
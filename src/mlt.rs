@@ -0,0 +1,124 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::scene::*;
+use crate::camera::*;
+use crate::integrator::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// primary sample space metropolis light transport (Kelemen et al. 2002) on
+// top of the path tracer. each chain wanders through the random numbers a
+// path is built from, lingering where the image is bright, so light that
+// only reaches the camera along a few narrow routes gets found again once
+// it's been found once. a bootstrap pass of independent paths fixes the
+// overall brightness, so the result matches the path tracer's
+pub struct MltIntegrator {
+    pub path: PathIntegrator,
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub mutations_per_pixel: usize,
+    pub sigma: f64, // size of a small step in primary sample space
+    pub large_step_probability: f64
+}
+
+impl Integrate for MltIntegrator {
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.path.li(r, scene, lambda, sampler)
+    }
+
+    fn render(&self, cam: &Camera, scene: &Scene) -> Option<Vec<Colour>> {
+        let (width, height) = (cam.image_width(), cam.image_height());
+        let pixels = (width * height) as usize;
+
+        // the bootstrap paths are the first paths of samplers seeded by
+        // their index, so a chain can start again from any of them
+        let weights: Vec<f64> = (0..self.bootstrap_samples).into_par_iter().map(|i| {
+            let mut sampler = self.sampler(i as u64);
+            self.trace(cam, scene, &mut sampler).1.luminance().max(0.0)
+        }).collect();
+
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 || self.chains == 0 { return Some(vec![colour(0, 0, 0); pixels]); }
+        let b = total / weights.len() as f64; // mean brightness over the film
+        let cdf: Vec<f64> = weights.iter().scan(0.0, |sum, w| { *sum += w; Some(*sum) }).collect();
+
+        let mutations = self.mutations_per_pixel * pixels;
+        let done = AtomicI32::new(0);
+
+        let film = (0..self.chains).into_par_iter().fold(|| vec![colour(0, 0, 0); pixels], |mut film, chain| {
+            let mut rng = StdRng::seed_from_u64((self.bootstrap_samples + chain) as u64);
+
+            // start from a bootstrap path picked in proportion to its brightness
+            let u = rng.gen::<f64>() * total;
+            let seed = cdf.partition_point(|c| *c <= u).min(cdf.len() - 1);
+            let mut sampler = self.sampler(seed as u64);
+            let (mut current, mut l) = self.trace(cam, scene, &mut sampler);
+            let mut i = l.luminance().max(0.0);
+
+            let chain_mutations = mutations * (chain + 1) / self.chains - mutations * chain / self.chains;
+            for _ in 0..chain_mutations {
+                metropolis(&mut sampler).start_iteration();
+                let (proposed, l_proposed) = self.trace(cam, scene, &mut sampler);
+                let i_proposed = l_proposed.luminance().max(0.0);
+                let accept = if i > 0.0 { (i_proposed / i).min(1.0) } else { 1.0 };
+
+                // both paths splat what they're expected to contribute,
+                // rather than only whichever one the chain moves to
+                if accept > 0.0 { film[pixel(proposed, width, height)] += l_proposed * (accept / i_proposed); }
+                if accept < 1.0 { film[pixel(current, width, height)] += l * ((1.0 - accept) / i); }
+
+                if rng.gen::<f64>() < accept {
+                    (current, l, i) = (proposed, l_proposed, i_proposed);
+                    metropolis(&mut sampler).accept();
+                } else {
+                    metropolis(&mut sampler).reject();
+                }
+            }
+
+            print_progress(done.fetch_add(1, Ordering::Relaxed), self.chains as i32, "chains");
+            film
+        }).reduce(|| vec![colour(0, 0, 0); pixels], |mut a, b| {
+            for (x, y) in a.iter_mut().zip(b) { *x += y; }
+            a
+        });
+
+        // splats landed at a density proportional to brightness over b
+        let scale = b / self.mutations_per_pixel.max(1) as f64;
+        Some(film.into_iter().map(|px| scale * px).collect())
+    }
+}
+
+impl MltIntegrator {
+    fn sampler(&self, seed: u64) -> Sampler {
+        Sampler::Metropolis(MltSampler::new(seed, self.sigma, self.large_step_probability))
+    }
+
+    // a path through a raster position taken from the sampler, so that
+    // mutations move it across the film too
+    fn trace(&self, cam: &Camera, scene: &Scene, sampler: &mut Sampler) -> ((f64, f64), Colour) {
+        let (u, v) = sampler.next_2d();
+        let (x, y) = (u * cam.image_width() as f64, v * cam.image_height() as f64);
+        let mut lambda = cam.wavelengths(sampler);
+        let r = cam.get_ray_at(x, y, sampler);
+        let c = self.path.li(&r, scene, &mut lambda, sampler);
+        ((x, y), lambda.to_rgb(c))
+    }
+}
+
+fn metropolis(sampler: &mut Sampler) -> &mut MltSampler {
+    match sampler {
+        Sampler::Metropolis(s) => s,
+        _ => unreachable!("chains only use metropolis samplers")
+    }
+}
+
+#[inline(always)]
+fn pixel((x, y): (f64, f64), width: i32, height: i32) -> usize {
+    let i = (x as i32).clamp(0, width - 1);
+    let j = (y as i32).clamp(0, height - 1);
+    (j * width + i) as usize
+}
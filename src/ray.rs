@@ -1,14 +1,17 @@
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     ori: Point3,
-    dir: Point3
+    dir: Point3,
+    inv_dir: Vec3 // cached for slab tests
 }
 
 impl Ray {
     pub fn new(orig: Point3, dire: Vec3) -> Self {
-        Self { ori: orig, dir: dire }
+        let inv_dir = Vec3::new(1.0 / dire.x(), 1.0 / dire.y(), 1.0 / dire.z());
+        Self { ori: orig, dir: dire, inv_dir }
     }
 
     pub fn at(&self, t: f64) -> Point3 {
@@ -22,6 +25,10 @@ impl Ray {
     pub fn dir(&self) -> Point3 {
         self.dir
     }
+
+    pub fn inv_dir(&self) -> Vec3 {
+        self.inv_dir
+    }
 }
 
 #[inline(always)]
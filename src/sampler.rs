@@ -0,0 +1,145 @@
+use enum_dispatch::enum_dispatch;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::f64::consts::PI;
+
+// fresh random numbers every time
+pub struct IndependentSampler {
+    rng: Box<StdRng> // boxed like MltSampler's, to keep Sampler small
+}
+
+// primary sample space metropolis (Kelemen et al. 2002). each number asked
+// for is a coordinate of one point in the unit hypercube, and a mutation
+// moves that point either a little or to somewhere new. coordinates are
+// only mutated once they're asked for, so paths of any length can be
+// replayed and rejected
+pub struct MltSampler {
+    rng: Box<StdRng>, // boxed so independent samplers stay small
+    sigma: f64, // standard deviation of a small step
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    iteration: i64,
+    large_step: bool,
+    last_large_step: i64,
+    index: usize
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modified: i64,
+    backup: f64,
+    backup_modified: i64
+}
+
+#[enum_dispatch]
+pub trait Sample {
+    // uniform on [0, 1)
+    fn next_1d(&mut self) -> f64;
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+#[enum_dispatch(Sample)]
+pub enum Sampler {
+    Independent(IndependentSampler),
+    Metropolis(MltSampler)
+}
+
+impl Sample for IndependentSampler {
+    fn next_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+impl Sample for MltSampler {
+    fn next_1d(&mut self) -> f64 {
+        let i = self.index;
+        self.index += 1;
+        self.ensure_ready(i);
+        self.x[i].value
+    }
+}
+
+impl IndependentSampler {
+    pub fn new() -> Self {
+        Self { rng: Box::new(StdRng::from_rng(thread_rng()).unwrap()) }
+    }
+
+    // the same numbers for the same seed, for results that mustn't change
+    // from run to run
+    pub fn seeded(seed: u64) -> Self {
+        Self { rng: Box::new(StdRng::seed_from_u64(seed)) }
+    }
+}
+
+impl Default for IndependentSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MltSampler {
+    // the same seed gives the same first path, which is how chains restart
+    // from a bootstrap sample
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: Box::new(StdRng::seed_from_u64(seed)),
+            sigma,
+            large_step_probability,
+            x: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0
+        }
+    }
+
+    // call before tracing each proposed path
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step { self.last_large_step = self.iteration; }
+    }
+
+    // puts back every coordinate the proposal changed
+    pub fn reject(&mut self) {
+        for xi in self.x.iter_mut().filter(|xi| xi.last_modified == self.iteration) {
+            xi.value = xi.backup;
+            xi.last_modified = xi.backup_modified;
+        }
+        self.iteration -= 1;
+    }
+
+    fn ensure_ready(&mut self, i: usize) {
+        if i >= self.x.len() { self.x.resize(i + 1, PrimarySample::default()); }
+        let xi = &mut self.x[i];
+
+        // coordinates untouched since the last large step are as good as new
+        if xi.last_modified < self.last_large_step {
+            xi.value = self.rng.gen();
+            xi.last_modified = self.last_large_step;
+        }
+
+        xi.backup = xi.value;
+        xi.backup_modified = xi.last_modified;
+        if self.large_step {
+            xi.value = self.rng.gen();
+        } else {
+            // the small steps it missed add up to one wider step
+            let skipped = (self.iteration - xi.last_modified) as f64;
+            let (u1, u2): (f64, f64) = (self.rng.gen(), self.rng.gen());
+            let normal = (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * PI * u2).cos();
+            xi.value += normal * self.sigma * skipped.sqrt();
+            xi.value -= xi.value.floor();
+            if xi.value >= 1.0 { xi.value = 0.0; } // rounding of tiny negatives
+        }
+        xi.last_modified = self.iteration;
+    }
+}
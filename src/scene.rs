@@ -0,0 +1,70 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::light::*;
+use crate::material::*;
+use crate::medium::*;
+use crate::sampler::*;
+use crate::background::*;
+use crate::spectrum::*;
+use std::sync::Arc;
+
+// everything an integrator needs to know about the world
+pub struct Scene {
+    pub world: Hittable,
+    pub lights: Lights,
+    pub background: Background,
+    pub medium: Option<Arc<Medium>> // around everything, e.g. haze
+}
+
+impl Scene {
+    pub fn new(world: Hittable) -> Self {
+        let lights = Lights::new(&world);
+        let background = Gradient { bottom: colour(1, 1, 1), top: colour(0.5, 0.7, 1.0) }.into();
+        Self { world, lights, background, medium: None }
+    }
+
+    #[inline(always)]
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.world.hit(r, ray_t)
+    }
+
+    // medium a ray leaving `rec` along `dir` travels through, having arrived
+    // through `current`. media don't nest, so leaving any boundary goes back
+    // to the scene's own medium
+    pub fn medium_after(&self, rec: &HitRecord, dir: &Vec3, current: Option<Arc<Medium>>) -> Option<Arc<Medium>> {
+        if dot(dir, &rec.geo_normal) >= 0.0 { return current; } // stayed on the same side
+        if rec.front_face { rec.material.medium() } else { self.medium.clone() }
+    }
+
+    // fraction of light getting from r.at(0) to r.at(t_max), starting out in
+    // `medium`. medium boundaries let it through, anything else blocks it
+    pub fn transmittance(&self, r: &Ray, t_max: f64, medium: Option<Arc<Medium>>, lambda: &Wavelengths,
+                         sampler: &mut Sampler) -> Colour {
+        let mut tr = colour(1, 1, 1);
+        let end = r.at(t_max);
+        let (mut r, mut t_max, mut medium) = (*r, t_max, medium);
+
+        loop {
+            let hit = self.hit(&r, interval(0.001, t_max));
+            if let Some(m) = &medium {
+                tr = tr * m.transmittance(&r, hit.as_ref().map_or(t_max, |rec| rec.t), lambda, sampler);
+            }
+
+            let Some(rec) = hit else { return tr; };
+            if !rec.material.is_interface() || tr.near_zero() { return colour(0, 0, 0); }
+
+            medium = self.medium_after(&rec, &r.dir(), medium);
+            r = rec.spawn_ray(r.dir());
+            // measured to the end rather than by what's left, so offsets
+            // from each spawn can't add up past it
+            if t_max.is_finite() { t_max = dot(&(end - r.ori()), &r.dir()) / r.dir().norm_sq(); }
+        }
+    }
+
+    // light arriving along rays that leave the scene
+    pub fn background(&self, r: &Ray, lambda: &Wavelengths) -> Colour {
+        lambda.spectral(self.background.radiance(&unit_vector(r.dir())))
+    }
+}
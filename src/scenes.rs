@@ -0,0 +1,262 @@
+use crate::vec3::*;
+use crate::hittable::*;
+use crate::camera::*;
+use crate::material::*;
+use crate::bvh::*;
+use crate::mesh::*;
+use crate::texture::*;
+use crate::scene::*;
+use crate::light::*;
+use crate::medium::*;
+use crate::voxel::*;
+use crate::background::*;
+use crate::ies::*;
+use crate::ior::*;
+use crate::util::*;
+use std::sync::Arc;
+use tobj::{self, LoadOptions};
+
+// files from the command line for the scenes that use them, and settings
+// that apply to every scene
+pub struct SceneOptions {
+    pub environment: Option<String>, // equirectangular image to light any scene with
+    pub texture: Option<String>, // image wrapped around a sphere in the textures scene
+    pub wrap: WrapMode,
+    pub grid: Option<String>, // .vol, or raw f32s with grid_size, for the volumes scene
+    pub grid_size: Option<(usize, usize, usize)>,
+    pub ies: Option<String>, // profile for the lights scene's point light
+    pub spectral: bool
+}
+
+pub fn build(name: &str, options: &SceneOptions) -> Result<(Scene, Camera), String> {
+    let (mut scene, mut cam) = match name {
+        "suzanne" => suzanne()?,
+        "materials" => materials(),
+        "textures" => textures(options)?,
+        "volumes" => volumes(options)?,
+        "lights" => lights(options)?,
+        "sky" => sky(),
+        _ => return Err(format!("unknown scene '{name}'"))
+    };
+
+    if let Some(path) = &options.environment {
+        let env = Environment::load(path, 0.0, 1.0).map_err(|e| format!("can't load '{path}': {e}"))?;
+        scene.background = env.into();
+    }
+    cam.spectral = options.spectral;
+    Ok((scene, cam))
+}
+
+fn camera(look_from: Point3, look_at: Point3, vfov: f64) -> Camera {
+    let mut cam = Camera::new(16.0/9.0, 500);
+    cam.sample_rate = 50;
+    cam.vfov = vfov;
+    cam.look_from = look_from;
+    cam.look_at = look_at;
+    cam.vup = vec3(0, 1, 0);
+    cam.defocus_angle = 0.0;
+    cam.focus_distance = (look_from - look_at).norm();
+    cam.reinit()
+}
+
+fn lambertian(albedo: Colour) -> Arc<Material> {
+    Arc::new(Material::Lambertian(Lambertian { albedo: solid(albedo) }))
+}
+
+fn sphere(world: &mut HittableList, centre: Point3, radius: f64, material: Material) {
+    world.add(Arc::new(Hittable::Sphere(Sphere::new(centre, radius, Arc::new(material)))));
+}
+
+fn ground(world: &mut HittableList, material: Arc<Material>) {
+    world.add(Arc::new(Hittable::Sphere(Sphere::new(point3(0, -1000, 0), 1000.0, material))));
+}
+
+// brushed aluminium suzanne next to a sphere
+fn suzanne() -> Result<(Scene, Camera), String> {
+    let mut world = HittableList::new();
+    let mat = Arc::new(Material::Metal(Metal::aluminium(constant(0.3))));
+
+    let load_options = LoadOptions { single_index: false, triangulate: true, ignore_points: true, ignore_lines: true };
+    let (models, _materials) = tobj::load_obj("suzanne.obj", &load_options).map_err(|e| format!("can't load suzanne.obj: {e}"))?;
+    for m in models.iter() {
+        world.add(Arc::new(Hittable::Mesh(Mesh::new(&m.mesh, mat.clone()))));
+    }
+
+    world.add(Arc::new(Hittable::Sphere(Sphere::new(point3(-2,0,2),1.0,mat.clone()))));
+    world.add(Arc::new(Hittable::Sphere(Sphere::new(point3(0,-102,0),100.0,lambertian(colour(0.5, 0.5, 0.5))))));
+
+    let mut cam = camera(point3(5.0, 0, 5.0), point3(0, 0, 0), 20.0);
+    cam.focus_distance = 2.0;
+    Ok((Scene::new(Hittable::Bvh(Bvh::new(world))), cam.reinit()))
+}
+
+// measured metals and principled surfaces at the back, dispersive and
+// rough tinted glass at the front
+fn materials() -> (Scene, Camera) {
+    let mut world = HittableList::new();
+    ground(&mut world, lambertian(colour(0.5, 0.5, 0.5)));
+
+    let mut brushed = Metal::aluminium(constant(0.25));
+    brushed.anisotropy = constant(0.8);
+    let mut paint = Principled::new(solid(colour(0.6, 0.05, 0.05)));
+    paint.clearcoat = constant(1.0);
+    paint.clearcoat_roughness = constant(0.05);
+    let mut velvet = Principled::new(solid(colour(0.1, 0.1, 0.4)));
+    velvet.roughness = constant(1.0);
+    velvet.sheen = constant(1.0);
+    let mut frosted = Principled::new(solid(colour(0.8, 1.0, 0.9)));
+    frosted.transmission = constant(1.0);
+    frosted.roughness = constant(0.2);
+
+    let back = vec![
+        Material::Metal(Metal::gold(constant(0.2))),
+        Material::Metal(Metal::copper(constant(0.1))),
+        Material::Metal(brushed),
+        Material::Principled(Principled::new(solid(colour(0.9, 0.6, 0.1)))),
+        Material::Principled(paint),
+        Material::Principled(velvet),
+        Material::Principled(frosted)
+    ];
+    let n = back.len();
+    for (i, m) in back.into_iter().enumerate() {
+        sphere(&mut world, point3(i as f64 - 0.5 * (n - 1) as f64, 0.45, -0.5), 0.45, m);
+    }
+
+    let glass = |mu: Ior| Material::Dielectric(Dielectric { mu, roughness: constant(0.0), medium: None });
+    let tint = Arc::new(Medium::Homogeneous(HomogeneousMedium::absorbing(colour(0.9, 0.4, 0.2), 0.8)));
+    let front = [
+        glass(Ior::bk7()),
+        glass(Ior::fused_silica()),
+        glass(Ior::diamond()),
+        glass(Ior::Cauchy { a: 1.6, b: 0.02 }),
+        Material::Dielectric(Dielectric { mu: Ior::Constant(1.5), roughness: constant(0.15), medium: Some(tint) })
+    ];
+    for (i, m) in front.into_iter().enumerate() {
+        sphere(&mut world, point3(i as f64 - 2.0, 0.4, 0.8), 0.4, m);
+    }
+
+    let mut scene = Scene::new(Hittable::Bvh(Bvh::new(world)));
+    scene.lights.add(DirectionalLight { direction: vec3(-1, -2, -1), irradiance: colour(3, 3, 3) }.into());
+    (scene, camera(point3(0, 2.5, 6), point3(0, 0.3, 0), 40.0))
+}
+
+// procedural patterns in world space and surface coordinates
+fn textures(options: &SceneOptions) -> Result<(Scene, Camera), String> {
+    let mut world = HittableList::new();
+    let checker = |inv_scale: f64, mapping: Mapping| Arc::new(Texture::Checker(Checker {
+        inv_scale,
+        even: solid(colour(0.2, 0.3, 0.1)),
+        odd: solid(colour(0.9, 0.9, 0.9)),
+        mapping
+    }));
+    ground(&mut world, Arc::new(Material::Lambertian(Lambertian { albedo: checker(2.0, Mapping::Position) })));
+
+    let noise = |pattern: NoisePattern, scale: f64, a: Colour, b: Colour| Arc::new(Texture::Noise(Noise::new(pattern, scale, a, b)));
+    let mut albedos = vec![
+        checker(10.0, Mapping::Uv),
+        noise(NoisePattern::Fbm, 4.0, colour(0.1, 0.1, 0.1), colour(0.9, 0.9, 0.9)),
+        noise(NoisePattern::Turbulence, 4.0, colour(0.9, 0.5, 0.1), colour(0.2, 0.05, 0.0)),
+        noise(NoisePattern::Marble, 3.0, colour(0.9, 0.9, 0.85), colour(0.2, 0.2, 0.25)),
+        noise(NoisePattern::Wood, 6.0, colour(0.6, 0.4, 0.2), colour(0.3, 0.15, 0.05))
+    ];
+    if let Some(path) = &options.texture {
+        let mut image = ImageTexture::load(path).map_err(|e| format!("can't load '{path}': {e}"))?;
+        image.wrap = options.wrap;
+        albedos.push(Arc::new(Texture::ImageTexture(image)));
+    }
+
+    let n = albedos.len();
+    for (i, albedo) in albedos.into_iter().enumerate() {
+        let x = 1.1 * (i as f64 - 0.5 * (n - 1) as f64);
+        sphere(&mut world, point3(x, 0.5, 0), 0.5, Material::Lambertian(Lambertian { albedo }));
+    }
+
+    let mut scene = Scene::new(Hittable::Bvh(Bvh::new(world)));
+    scene.lights.add(DirectionalLight { direction: vec3(1, -2, -1.5), irradiance: colour(2.5, 2.5, 2.5) }.into());
+    Ok((scene, camera(point3(0, 2, 6), point3(0, 0.4, 0), 45.0)))
+}
+
+// fog, tinted glass and smoke, from a grid file if there's one or else a
+// procedural puff
+fn volumes(options: &SceneOptions) -> Result<(Scene, Camera), String> {
+    let mut world = HittableList::new();
+    ground(&mut world, lambertian(colour(0.6, 0.6, 0.6)));
+
+    let fog = Arc::new(Medium::Homogeneous(HomogeneousMedium::new(colour(0.05, 0.05, 0.05), colour(1.5, 1.5, 1.5), 0.3)));
+    let boundary = Arc::new(Hittable::Sphere(Sphere::new(point3(-2, 0.7, 0), 0.7, Arc::new(Material::Interface(Interface { medium: None })))));
+    world.add(Arc::new(Hittable::ConstantMedium(ConstantMedium::new(boundary, fog))));
+
+    let tint = Arc::new(Medium::Homogeneous(HomogeneousMedium::absorbing(colour(0.2, 0.6, 0.9), 1.0)));
+    sphere(&mut world, point3(0, 0.7, 0), 0.7, Material::Dielectric(Dielectric { mu: Ior::Constant(1.5), roughness: constant(0.0), medium: Some(tint) }));
+
+    let grid = match (&options.grid, options.grid_size) {
+        (Some(path), Some((nx, ny, nz))) => VoxelGrid::load_raw(path, nx, ny, nz),
+        (Some(path), None) => VoxelGrid::load_vol(path),
+        (None, _) => puff(32)
+    }.map_err(|e| format!("can't load grid: {e}"))?;
+    let bounds = Aabb::from_points(point3(1.3, 0, -0.7), point3(2.7, 1.4, 0.7));
+    let smoke = GridMedium::new(grid.sparse(), bounds, 8.0, colour(0.9, 0.9, 0.9), 0.0, 16);
+    world.add(Arc::new(Hittable::GridVolume(GridVolume::new(smoke))));
+
+    let mut scene = Scene::new(Hittable::Bvh(Bvh::new(world)));
+    scene.lights.add(DirectionalLight { direction: vec3(-1, -2, -1), irradiance: colour(3, 3, 3) }.into());
+    Ok((scene, camera(point3(0, 2, 6), point3(0, 0.6, 0), 45.0)))
+}
+
+// density falling off from the middle of an n^3 grid, thinning towards
+// the edges so most of the bricks are empty
+fn puff(n: usize) -> std::io::Result<VoxelGrid> {
+    let values = (0..n * n * n).map(|idx| {
+        let (i, j, k) = (idx % n, (idx / n) % n, idx / (n * n));
+        let d = point3(i as f64 + 0.5, j as f64 + 0.5, k as f64 + 0.5) / n as f64 - vec3(0.5, 0.5, 0.5);
+        (1.0 - 2.5 * d.norm()).max(0.0) as f32
+    }).collect();
+    VoxelGrid::new(n, n, n, values)
+}
+
+// a room lit only by lights with no size
+fn lights(options: &SceneOptions) -> Result<(Scene, Camera), String> {
+    let mut world = HittableList::new();
+    ground(&mut world, lambertian(colour(0.7, 0.7, 0.7)));
+    let white = lambertian(colour(0.7, 0.7, 0.7));
+    world.add(Arc::new(Hittable::Triangle(Triangle::new(point3(-4, 0, -2), point3(4, 0, -2), point3(4, 4, -2), white.clone()))));
+    world.add(Arc::new(Hittable::Triangle(Triangle::new(point3(-4, 0, -2), point3(4, 4, -2), point3(-4, 4, -2), white))));
+    sphere(&mut world, point3(-1.2, 0.5, 0), 0.5, Material::Principled(Principled::new(solid(colour(0.8, 0.2, 0.2)))));
+    sphere(&mut world, point3(1.2, 0.5, 0), 0.5, Material::Metal(Metal::copper(constant(0.2))));
+
+    let mut scene = Scene::new(Hittable::Bvh(Bvh::new(world)));
+    scene.background = Uniform { radiance: colour(0, 0, 0) }.into();
+
+    // a profile sets the light's shape, and its peak how bright it is
+    let (profile, intensity) = match &options.ies {
+        Some(path) => {
+            let profile = IesProfile::load(path).map_err(|e| format!("can't load '{path}': {e}"))?;
+            let peak = profile.peak() / 1000.0;
+            (Some(Arc::new(profile)), colour(peak, peak, peak))
+        }
+        None => (None, colour(4, 4, 4))
+    };
+    scene.lights.add(PointLight { position: point3(-1.2, 3, 0.5), intensity, profile }.into());
+    scene.lights.add(SpotLight {
+        position: point3(2, 3, 2),
+        direction: vec3(-0.8, -3, -2),
+        intensity: colour(20, 16, 10),
+        cone_angle: 25.0,
+        falloff_start: 15.0,
+        profile: None
+    }.into());
+    scene.lights.add(DirectionalLight { direction: vec3(1, -1, -1), irradiance: colour(0.2, 0.25, 0.4) }.into());
+    Ok((scene, camera(point3(0, 2, 6), point3(0, 0.8, 0), 45.0)))
+}
+
+// late afternoon daylight
+fn sky() -> (Scene, Camera) {
+    let mut world = HittableList::new();
+    ground(&mut world, lambertian(colour(0.4, 0.4, 0.4)));
+    sphere(&mut world, point3(-1.1, 1, 0), 1.0, Material::Principled(Principled::new(solid(colour(0.8, 0.8, 0.8)))));
+    sphere(&mut world, point3(1.1, 1, 0), 1.0, Material::Metal(Metal::gold(constant(0.1))));
+
+    let mut scene = Scene::new(Hittable::Bvh(Bvh::new(world)));
+    scene.background = Sky::new(vec3(-1, 0.4, -1), 3.0, colour(0.4, 0.4, 0.4), 0.05).into();
+    (scene, camera(point3(0, 1.5, 7), point3(0, 0.8, 0), 40.0))
+}
@@ -0,0 +1,284 @@
+use crate::vec3::*;
+use std::sync::OnceLock;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+const STEPS: usize = 94; // 5 nm apart, for integrating over the visible range
+const RES: usize = 32; // of the rgb to spectrum table, per axis
+
+// the wavelengths a path carries, in nm, and the densities they were picked
+// with, passed along the path like its sampler. the first is the hero, the
+// others are spread evenly round the visible range from it and get dropped
+// if something, like dispersion, only lets one wavelength through. colours
+// pass through rgb paths unchanged, and they only carry the hero, for if
+// they're dispersed
+#[derive(Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    pub pdf: [f64; 3],
+    rgb: bool,
+    terminated: bool
+}
+
+impl Wavelengths {
+    // importance sampled towards where the eye is most sensitive
+    pub fn sample(u: f64) -> Self {
+        let mut lambda = [0.0; 3];
+        let mut pdf = [0.0; 3];
+        for i in 0..3 {
+            let ui = (u + i as f64 / 3.0).fract();
+            lambda[i] = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * ui).atanh();
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Self { lambda, pdf, rgb: false, terminated: false }
+    }
+
+    // for colours looked at in rgb outside of any path, which can't be
+    // dispersed
+    pub const RGB: Self = Self { lambda: [0.0; 3], pdf: [0.0; 3], rgb: true, terminated: false };
+
+    // for a path rendered in rgb. the hero is picked up front, so every
+    // part of a path that's dispersed is bent at the same wavelength
+    pub fn rgb(u: f64) -> Self {
+        Self { rgb: true, ..Self::sample(u) }
+    }
+
+    // an rgb colour as the path sees it: unchanged when rendering in rgb,
+    // or its spectrum's values at the path's wavelengths
+    pub fn spectral(&self, c: Colour) -> Colour {
+        if self.rgb { return c; }
+        colour(rgb_spectrum(&c, self.lambda[0]), rgb_spectrum(&c, self.lambda[1]), rgb_spectrum(&c, self.lambda[2]))
+    }
+
+    // a spectrum known at any wavelength, like a measured one, as the path
+    // sees it. `rgb` stands in for it on rgb paths
+    pub fn sampled(&self, rgb: Colour, f: impl Fn(f64) -> f64) -> Colour {
+        if self.rgb { return rgb; }
+        colour(f(self.lambda[0]), f(self.lambda[1]), f(self.lambda[2]))
+    }
+
+    // the linear rgb a spectrum looks like, from its values at these
+    // wavelengths
+    pub fn to_rgb(self, values: Colour) -> Colour {
+        if self.rgb { return values; }
+        let mut rgb = colour(0, 0, 0);
+        for i in 0..3 {
+            if self.pdf[i] > 0.0 { rgb += (values[i] / self.pdf[i]) * film_response(self.lambda[i]); }
+        }
+        rgb / 3.0
+    }
+
+    // the wavelength something dispersive bends the path at, and what to
+    // scale its throughput by the first time. spectral paths drop all but
+    // the hero, which would have gone elsewhere, so it stands in for them
+    // too. rgb paths take on the hero's colour, which averages out to
+    // white over many paths
+    pub fn disperse(&mut self) -> (f64, Colour) {
+        if self.terminated { return (self.lambda[0], colour(1, 1, 1)); }
+        self.terminated = true;
+        (self.lambda[0], self.tint())
+    }
+
+    // what to scale the throughput of a path joined from subpaths carrying
+    // `self` and `other` by. each took on the tint if it was dispersed, but
+    // the whole path only should once
+    pub fn joined(&self, other: &Wavelengths) -> Colour {
+        if !(self.terminated && other.terminated) { return colour(1, 1, 1); }
+        let tint = self.tint();
+        let inv = |c: f64| if c != 0.0 { 1.0 / c } else { 0.0 };
+        colour(inv(tint.x()), inv(tint.y()), inv(tint.z()))
+    }
+
+    #[inline(always)]
+    fn tint(&self) -> Colour {
+        if self.rgb { film_response(self.lambda[0]) / self.pdf[0] } else { colour(3, 0, 0) }
+    }
+}
+
+#[inline(always)]
+fn visible_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) { return 0.0; }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+// cie 1931 colour matching functions, from the multi-lobe fit of Wyman et
+// al. 2013
+fn cie_xyz(lambda: f64) -> Colour {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    colour(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+    )
+}
+
+// rgb the film records per unit of light at `lambda`, balanced so that a
+// flat spectrum comes out white
+fn film_response(lambda: f64) -> Colour {
+    static WHITE: OnceLock<Colour> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+        (0..STEPS).fold(colour(0, 0, 0), |acc, n| acc + dl * cie_xyz(step(n))).xyz_to_rgb()
+    });
+
+    let rgb = cie_xyz(lambda).xyz_to_rgb();
+    colour(rgb.x() / white.x(), rgb.y() / white.y(), rgb.z() / white.z())
+}
+
+#[inline(always)]
+fn step(n: usize) -> f64 {
+    LAMBDA_MIN + (n as f64 + 0.5) * (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64
+}
+
+// smooth spectra through rgb colours, the sigmoid of a quadratic in
+// wavelength (Jakob and Hanika 2019). coefficients are fitted for colours
+// whose largest channel is 1, and scaled to the rest, so reflectances stay
+// below 1 and scaling a colour scales its spectrum
+struct SigmoidTable {
+    coeffs: Vec<[f64; 3]> // [largest channel][other channels, on a RES x RES grid]
+}
+
+// the value at `lambda` of colour `c`'s spectrum
+fn rgb_spectrum(c: &Colour, lambda: f64) -> f64 {
+    static TABLE: OnceLock<SigmoidTable> = OnceLock::new();
+    let c = colour(c.x().max(0.0), c.y().max(0.0), c.z().max(0.0));
+    let m = c.max_component();
+    if m <= 0.0 { return 0.0; }
+
+    let k = if c.x() >= c.y() && c.x() >= c.z() { 0 } else if c.y() >= c.z() { 1 } else { 2 };
+    let (a, b) = others(k);
+    let coeffs = TABLE.get_or_init(SigmoidTable::fit).lookup(k, c[a] / m, c[b] / m);
+    m * sigmoid_spectrum(&coeffs, lambda)
+}
+
+impl SigmoidTable {
+    // gauss-newton with levenberg-marquardt damping, starting each colour
+    // from a neighbour already fitted and working out from white
+    fn fit() -> Self {
+        let weights: Vec<(f64, Colour)> = (0..STEPS).map(|n| {
+            let lambda = step(n);
+            (normalised(lambda), (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64 * film_response(lambda))
+        }).collect();
+
+        let mut coeffs = vec![[0.0; 3]; 3 * RES * RES];
+        for k in 0..3 {
+            let (a, b) = others(k);
+            let mut start = [0.0; 3];
+            for j in (0..RES).rev() {
+                for i in (0..RES).rev() {
+                    if i == RES - 1 && j < RES - 1 { start = coeffs[(k * RES + j + 1) * RES + i]; }
+                    let mut target = colour(0, 0, 0);
+                    target[k] = 1.0;
+                    target[a] = i as f64 / (RES - 1) as f64;
+                    target[b] = j as f64 / (RES - 1) as f64;
+
+                    start = fit_sigmoid(&weights, &target, start);
+                    coeffs[(k * RES + j) * RES + i] = start;
+                }
+            }
+        }
+
+        Self { coeffs }
+    }
+
+    // bilinear between the fitted colours around (k = 1, a, b)
+    fn lookup(&self, k: usize, a: f64, b: f64) -> [f64; 3] {
+        let (x, y) = (a * (RES - 1) as f64, b * (RES - 1) as f64);
+        let (i, j) = ((x as usize).min(RES - 2), (y as usize).min(RES - 2));
+        let (fx, fy) = (x - i as f64, y - j as f64);
+        let at = |i: usize, j: usize| self.coeffs[(k * RES + j) * RES + i];
+
+        std::array::from_fn(|n| {
+            (1.0 - fy) * ((1.0 - fx) * at(i, j)[n] + fx * at(i + 1, j)[n]) + fy * ((1.0 - fx) * at(i, j + 1)[n] + fx * at(i + 1, j + 1)[n])
+        })
+    }
+}
+
+fn fit_sigmoid(weights: &[(f64, Colour)], target: &Colour, start: [f64; 3]) -> [f64; 3] {
+    // rgb of the spectrum from coefficients `c`, and its derivatives
+    let eval = |c: &[f64; 3]| {
+        let mut rgb = colour(0, 0, 0);
+        let mut jac = [colour(0, 0, 0); 3];
+        for (x, w) in weights {
+            let p = (c[0] * x + c[1]) * x + c[2];
+            let d = 1.0 / (2.0 * (1.0 + p * p).powf(1.5));
+            rgb += sigmoid(p) * *w;
+            jac[0] += (d * x * x) * *w;
+            jac[1] += (d * x) * *w;
+            jac[2] += d * *w;
+        }
+        (rgb - *target, jac)
+    };
+
+    let mut c = start;
+    let (mut r, mut jac) = eval(&c);
+    let mut mu = 1.0e-3;
+    for _ in 0..100 {
+        if r.norm_sq() < 1.0e-12 { break; }
+
+        // (j^t j + mu) delta = -j^t r
+        let mut a = [[0.0; 3]; 3];
+        let mut g = [0.0; 3];
+        for p in 0..3 {
+            for q in 0..3 { a[p][q] = dot(&jac[p], &jac[q]) + if p == q { mu } else { 0.0 }; }
+            g[p] = -dot(&jac[p], &r);
+        }
+        let Some(delta) = solve3(&a, &g) else { break; };
+
+        let next = [c[0] + delta[0], c[1] + delta[1], c[2] + delta[2]];
+        let (r_next, jac_next) = eval(&next);
+        if r_next.norm_sq() < r.norm_sq() {
+            (c, r, jac) = (next, r_next, jac_next);
+            mu = (mu * 0.5).max(1.0e-9);
+        } else {
+            mu *= 4.0;
+        }
+    }
+    c
+}
+
+// cramer's rule
+fn solve3(a: &[[f64; 3]; 3], b: &[f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(a);
+    if d.abs() < 1.0e-300 { return None; }
+
+    Some(std::array::from_fn(|col| {
+        let mut m = *a;
+        for row in 0..3 { m[row][col] = b[row]; }
+        det(&m) / d
+    }))
+}
+
+#[inline(always)]
+fn sigmoid_spectrum(c: &[f64; 3], lambda: f64) -> f64 {
+    let x = normalised(lambda);
+    sigmoid((c[0] * x + c[1]) * x + c[2])
+}
+
+#[inline(always)]
+fn sigmoid(p: f64) -> f64 {
+    0.5 + p / (2.0 * (1.0 + p * p).sqrt())
+}
+
+#[inline(always)]
+fn normalised(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+// the two channels other than `k`, in order
+#[inline(always)]
+fn others(k: usize) -> (usize, usize) {
+    match k {
+        0 => (1, 2),
+        1 => (0, 2),
+        _ => (0, 1)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::vec3::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::scene::*;
+use crate::camera::*;
+use crate::integrator::*;
+use crate::medium::*;
+use crate::sampler::*;
+use crate::spectrum::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+// stochastic progressive photon mapping (Hachisuka and Jensen 2009). every
+// pass finds one visible point per pixel and scatters a batch of photons
+// from the lights, then folds the photons near each visible point into
+// that pixel's estimate while shrinking the radius it gathers over.
+// photons don't leave the background, so an environment map or sky only
+// lights what the camera sees directly or after one diffuse bounce, and
+// indirect light from it is never gathered. lights infinitely far away aim
+// their photons at what the camera sees. photons are shared by every
+// pixel, so they can't follow any one path's wavelengths, and it always
+// renders in rgb
+pub struct SppmIntegrator {
+    pub passes: i32,
+    pub photons_per_pass: usize,
+    pub initial_radius: f64, // as a fraction of the size of the region photons are aimed at
+    pub max_depth: i32
+}
+
+// share of each pass's photons a pixel keeps counting, 2/3 in the paper
+const ALPHA: f64 = 2.0 / 3.0;
+
+// first surface past any mirrors and glass that a camera path reaches
+struct VisiblePoint {
+    rec: HitRecord,
+    r_in: Ray,
+    beta: Colour // throughput from the camera
+}
+
+#[derive(Clone, Copy)]
+struct PixelState {
+    direct: Colour, // summed over passes, light found without photons
+    tau: Colour, // flux gathered so far, scaled to the current radius
+    n: f64, // photons counted so far
+    radius: f64
+}
+
+struct Photon {
+    p: Point3,
+    dir: Vec3, // unit direction of travel
+    power: Colour
+}
+
+// photons bucketed into the cells of a uniform grid, with cells no
+// smaller than any gather radius so a lookup only checks 27 of them
+struct PhotonGrid {
+    cell: f64,
+    cells: HashMap<(i64, i64, i64), Vec<Photon>>
+}
+
+impl Integrate for SppmIntegrator {
+    // photons only exist over a whole render, so a lone ray just gets the
+    // light reaching its visible point directly
+    fn li(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> Colour {
+        self.visible_point(r, scene, lambda, sampler).0
+    }
+
+    fn render(&self, cam: &Camera, scene: &Scene) -> Option<Vec<Colour>> {
+        if cam.spectral { eprintln!("sppm only renders in rgb, so the camera's spectral setting is ignored"); }
+        let width = cam.image_width();
+        let start = PixelState { direct: colour(0, 0, 0), tau: colour(0, 0, 0), n: 0.0, radius: 0.0 };
+        let mut pixels = vec![start; (width * cam.image_height()) as usize];
+
+        for pass in 0..self.passes {
+            print_progress(pass, self.passes, "passes");
+
+            let points: Vec<Option<VisiblePoint>> = pixels.par_iter_mut().enumerate().map(|(idx, px)| {
+                let mut sampler = Sampler::Independent(IndependentSampler::new());
+                let r = cam.get_ray(idx as i32 % width, idx as i32 / width, &mut sampler);
+                let mut lambda = Wavelengths::rgb(sampler.next_1d());
+                let (direct, vp) = self.visible_point(&r, scene, &mut lambda, &mut sampler);
+                px.direct += direct;
+                vp
+            }).collect();
+
+            let target = photon_target(&points, &cam.look_from);
+            if target.is_empty() { continue; }
+            if pixels.iter().all(|px| px.radius == 0.0) {
+                let radius = self.initial_radius * target.diagonal().norm();
+                pixels.iter_mut().for_each(|px| px.radius = radius);
+            }
+
+            let photons: Vec<Photon> = (0..self.photons_per_pass).into_par_iter()
+                .flat_map_iter(|_| self.trace_photon(scene, &target, &mut Sampler::Independent(IndependentSampler::new())))
+                .collect();
+            let max_radius = pixels.iter().map(|px| px.radius).fold(0.0, f64::max);
+            let grid = PhotonGrid::new(photons, max_radius);
+
+            pixels.par_iter_mut().zip(points.par_iter()).for_each(|(px, vp)| {
+                let Some(vp) = vp else { return; };
+                let (phi, m) = grid.gather(vp, px.radius);
+                if m == 0 { return; }
+
+                // keep only part of the new photons, and shrink the radius
+                // so the density estimate stays the same
+                let n = px.n + ALPHA * m as f64;
+                let radius = px.radius * (n / (px.n + m as f64)).sqrt();
+                px.tau = (px.tau + vp.beta * phi) * (radius * radius / (px.radius * px.radius));
+                px.n = n;
+                px.radius = radius;
+            });
+        }
+
+        let passes = self.passes.max(1) as f64;
+        let photons = passes * self.photons_per_pass as f64;
+        Some(pixels.iter().map(|px| {
+            let indirect = if px.n > 0.0 { px.tau / (photons * PI * px.radius * px.radius) } else { colour(0, 0, 0) };
+            px.direct / passes + indirect
+        }).collect())
+    }
+}
+
+impl SppmIntegrator {
+    // light reaching the camera along `r` without photons: emitters seen
+    // through mirrors and glass, and direct light at the visible point
+    fn visible_point(&self, r: &Ray, scene: &Scene, lambda: &mut Wavelengths, sampler: &mut Sampler) -> (Colour, Option<VisiblePoint>) {
+        let mut direct = colour(0, 0, 0);
+        let mut beta = colour(1, 1, 1);
+        let mut r = *r;
+
+        for _ in 0..self.max_depth {
+            let Some(rec) = scene.hit(&r, interval(0.001, f64::INFINITY)) else {
+                direct += beta * scene.background(&r, lambda);
+                break;
+            };
+            direct += beta * rec.material.emitted(&r, &rec, lambda);
+
+            let Some((scatrd_ray, attenuation)) = rec.material.scatter(&r, &rec, lambda, sampler) else { break; };
+            if rec.material.eval(&r, &rec, &scatrd_ray.dir(), lambda).is_none() {
+                beta = beta * attenuation;
+                r = scatrd_ray;
+                continue;
+            }
+
+            // light and material sampling, as in the direct lighting integrator
+            if let Some(d) = scene.lights.sample_direct(scene, &r, &Interaction::Surface(&rec), None, lambda, sampler) { direct += beta * d; }
+            let pdf = rec.material.pdf(&r, &rec, &scatrd_ray.dir());
+            direct += beta * attenuation * match scene.hit(&scatrd_ray, interval(0.001, f64::INFINITY)) {
+                Some(lrec) => weighted_emission(&scatrd_ray, &lrec, scene, Some((rec.p, pdf)), lambda),
+                None => weighted_background(&scatrd_ray, scene, Some((rec.p, pdf)), lambda)
+            };
+
+            return (direct, Some(VisiblePoint { rec, r_in: r, beta }));
+        }
+
+        (direct, None)
+    }
+
+    // follows one photon from an emitter, leaving a copy of it at every
+    // surface that can gather it
+    fn trace_photon(&self, scene: &Scene, target: &Aabb, sampler: &mut Sampler) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        let mut lambda = Wavelengths::rgb(sampler.next_1d());
+        let Some(e) = scene.lights.sample_emission(Some(target), &lambda, sampler) else { return photons; };
+        let mut power = e.power();
+        let mut r = e.ray;
+
+        for depth in 0..self.max_depth {
+            let Some(rec) = scene.hit(&r, interval(0.001, f64::INFINITY)) else { break; };
+
+            // visible points find the first hit by light sampling already
+            if depth > 0 && rec.material.eval(&r, &rec, &-r.dir(), &lambda).is_some() {
+                photons.push(Photon { p: rec.p, dir: unit_vector(r.dir()), power });
+            }
+
+            let Some((scatrd_ray, attenuation)) = rec.material.scatter(&r, &rec, &mut lambda, sampler) else { break; };
+            let scattered = power * attenuation;
+
+            // carry on in proportion to how much of the power the bounce kept
+            let before = power.max_component();
+            let survive = if before > 0.0 { (scattered.max_component() / before).min(1.0) } else { 0.0 };
+            if survive <= 0.0 || sampler.next_1d() >= survive { break; }
+
+            power = scattered / survive;
+            r = scatrd_ray;
+        }
+
+        photons
+    }
+}
+
+// box around the nearest nine tenths of the visible points, for lights
+// infinitely far away to aim at. the rest are mostly ground running off
+// to the horizon, too spread out to be worth the photons
+fn photon_target(points: &[Option<VisiblePoint>], eye: &Point3) -> Aabb {
+    let mut near: Vec<(f64, Point3)> = points.iter().flatten().map(|vp| ((vp.rec.p - *eye).norm_sq(), vp.rec.p)).collect();
+    if near.is_empty() { return Aabb::empty(); }
+
+    let keep = (9 * near.len()).div_ceil(10);
+    near.select_nth_unstable_by(keep - 1, |a, b| a.0.total_cmp(&b.0));
+    near[..keep].iter().fold(Aabb::empty(), |bounds, (_, p)| Aabb::surrounding(&bounds, &Aabb::from_points(*p, *p)))
+}
+
+impl PhotonGrid {
+    fn new(photons: Vec<Photon>, cell: f64) -> Self {
+        let mut grid = Self { cell: cell.max(1.0e-6), cells: HashMap::new() };
+        for photon in photons {
+            grid.cells.entry(grid.cell_of(&photon.p)).or_default().push(photon);
+        }
+        grid
+    }
+
+    #[inline(always)]
+    fn cell_of(&self, p: &Point3) -> (i64, i64, i64) {
+        ((p.x() / self.cell).floor() as i64, (p.y() / self.cell).floor() as i64, (p.z() / self.cell).floor() as i64)
+    }
+
+    // flux the photons within `radius` of the visible point send back to
+    // the camera, and how many there were
+    fn gather(&self, vp: &VisiblePoint, radius: f64) -> (Colour, usize) {
+        let mut phi = colour(0, 0, 0);
+        let mut m = 0;
+        let (x, y, z) = self.cell_of(&vp.rec.p);
+
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(cell) = self.cells.get(&(x + dx, y + dy, z + dz)) else { continue; };
+                    for photon in cell {
+                        if (photon.p - vp.rec.p).norm_sq() > radius * radius { continue; }
+                        m += 1;
+
+                        // the density of photons stands in for the cosine
+                        let wi = -photon.dir;
+                        let cosine = dot(&wi, &vp.rec.normal).abs();
+                        let Some(f) = vp.rec.material.eval(&vp.r_in, &vp.rec, &wi, &Wavelengths::RGB) else { continue; };
+                        if cosine > 1.0e-8 { phi += f / cosine * photon.power; }
+                    }
+                }
+            }
+        }
+
+        (phi, m)
+    }
+}
@@ -0,0 +1,314 @@
+use crate::vec3::*;
+use crate::util::*;
+use enum_dispatch::enum_dispatch;
+use image::DynamicImage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct SolidColour {
+    pub albedo: Colour
+}
+
+#[derive(Clone, Copy)]
+pub enum Mapping {
+    Uv, // surface coordinates
+    Position // world space hit point
+}
+
+pub struct Checker {
+    pub inv_scale: f64,
+    pub even: Arc<Texture>,
+    pub odd: Arc<Texture>,
+    pub mapping: Mapping
+}
+
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp
+}
+
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>, // linear, row 0 at the top
+    pub wrap: WrapMode
+}
+
+#[derive(Clone, Copy)]
+pub enum NoisePattern {
+    Fbm,
+    Turbulence,
+    Marble,
+    Wood
+}
+
+pub struct Noise {
+    perlin: Perlin,
+    pub scale: f64,
+    pub octaves: i32,
+    pub pattern: NoisePattern,
+    pub a: Colour, // colour where the pattern is 0
+    pub b: Colour // colour where the pattern is 1
+}
+
+// shows the surface coordinates as red and green, for checking uv layouts
+pub struct UvTexture;
+
+#[enum_dispatch]
+pub trait Evaluate {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Colour;
+
+    // for single channel parameters like roughness, from a grey texture
+    fn scalar(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let c = self.value(u, v, p);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+}
+
+#[enum_dispatch(Evaluate)]
+pub enum Texture {
+    SolidColour(SolidColour),
+    Checker(Checker),
+    ImageTexture(ImageTexture),
+    Noise(Noise),
+    UvTexture(UvTexture)
+}
+
+impl Evaluate for SolidColour {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Colour {
+        self.albedo
+    }
+}
+
+impl Evaluate for Checker {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Colour {
+        let sum = match self.mapping {
+            Mapping::Uv => (self.inv_scale * u).floor() as i64 + (self.inv_scale * v).floor() as i64,
+            Mapping::Position => (self.inv_scale * p.x()).floor() as i64
+                + (self.inv_scale * p.y()).floor() as i64
+                + (self.inv_scale * p.z()).floor() as i64
+        };
+
+        if sum % 2 == 0 { self.even.value(u, v, p) } else { self.odd.value(u, v, p) }
+    }
+}
+
+impl Evaluate for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Colour {
+        if self.pixels.is_empty() { return colour(1, 0, 1); } // easy to spot
+
+        // pixel centres sit at half integers, v = 0 is the bottom row
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        (1.0 - fx) * (1.0 - fy) * self.texel(x0, y0)
+            + fx * (1.0 - fy) * self.texel(x0 + 1, y0)
+            + (1.0 - fx) * fy * self.texel(x0, y0 + 1)
+            + fx * fy * self.texel(x0 + 1, y0 + 1)
+    }
+}
+
+impl Evaluate for Noise {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Colour {
+        let sp = self.scale * *p;
+        let t = match self.pattern {
+            NoisePattern::Fbm => 0.5 * (1.0 + self.perlin.fbm(&sp, self.octaves)),
+            NoisePattern::Turbulence => self.perlin.turbulence(&sp, self.octaves),
+            NoisePattern::Marble => 0.5 * (1.0 + (sp.z() + 10.0 * self.perlin.turbulence(&sp, self.octaves)).sin()),
+            NoisePattern::Wood => {
+                // rings around the y axis, wobbled by the noise
+                let rings = (sp.x()*sp.x() + sp.z()*sp.z()).sqrt() + 0.4 * self.perlin.turbulence(&sp, self.octaves);
+                rings - rings.floor()
+            }
+        };
+
+        let t = interval(0.0, 1.0).clamp(t);
+        (1.0 - t) * self.a + t * self.b
+    }
+}
+
+impl Evaluate for UvTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Colour {
+        colour(u - u.floor(), v - v.floor(), 0)
+    }
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Colour>) -> Self {
+        assert_eq!(width * height, pixels.len());
+        Self { width, height, pixels, wrap: WrapMode::Repeat }
+    }
+
+    // integer images are assumed to be sRGB encoded, float images linear
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let img = image::open(path)?;
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let linear = matches!(img, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+
+        let pixels = img.to_rgb32f().pixels().map(|px| {
+            let c = colour(px[0], px[1], px[2]);
+            if linear { c } else { colour(srgb_to_linear(c.x()), srgb_to_linear(c.y()), srgb_to_linear(c.z())) }
+        }).collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Colour {
+        self.pixels[y * self.width + x]
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Colour {
+        let x = wrap(x, self.width as i64, self.wrap);
+        let y = wrap(y, self.height as i64, self.wrap);
+        self.pixel(x, y)
+    }
+}
+
+fn wrap(i: i64, n: i64, mode: WrapMode) -> usize {
+    let i = match mode {
+        WrapMode::Repeat => i.rem_euclid(n),
+        WrapMode::Clamp => i.clamp(0, n - 1),
+        WrapMode::Mirror => {
+            let m = i.rem_euclid(2 * n);
+            if m < n { m } else { 2 * n - 1 - m }
+        }
+    };
+    i as usize
+}
+
+#[inline(always)]
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+impl Noise {
+    pub fn new(pattern: NoisePattern, scale: f64, a: Colour, b: Colour) -> Self {
+        Self { perlin: Perlin::new(0), scale, octaves: 7, pattern, a, b }
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+// gradient noise, as in Ken Perlin's improved noise but with random
+// gradients instead of the fixed twelve
+struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINTS).map(|_| {
+            loop {
+                let g = vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                if g.norm_sq() < 1.0 && !g.near_zero() { return unit_vector(g); }
+            }
+        }).collect();
+
+        let perm = |rng: &mut StdRng| -> Vec<usize> {
+            let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+            for i in (1..PERLIN_POINTS).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        let perm_x = perm(&mut rng);
+        let perm_y = perm(&mut rng);
+        let perm_z = perm(&mut rng);
+
+        Self { gradients, perm_x, perm_y, perm_z }
+    }
+
+    // in [-1, 1]
+    fn noise(&self, p: &Point3) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+        let mask = PERLIN_POINTS as i64 - 1;
+
+        // hermite smoothing of the cell coordinates
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut acc = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let g = self.gradients[
+                        self.perm_x[((i + di) & mask) as usize]
+                        ^ self.perm_y[((j + dj) & mask) as usize]
+                        ^ self.perm_z[((k + dk) & mask) as usize]
+                    ];
+                    let (a, b, c) = (di as f64, dj as f64, dk as f64);
+                    let weight = vec3(u - a, v - b, w - c);
+                    acc += (a*uu + (1.0 - a)*(1.0 - uu))
+                        * (b*vv + (1.0 - b)*(1.0 - vv))
+                        * (c*ww + (1.0 - c)*(1.0 - ww))
+                        * dot(&g, &weight);
+                }
+            }
+        }
+
+        acc
+    }
+
+    // fractional brownian motion, octaves of halving amplitude
+    fn fbm(&self, p: &Point3, octaves: i32) -> f64 {
+        let mut acc = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+        let mut total = 0.0;
+
+        for _ in 0..octaves {
+            acc += weight * self.noise(&temp_p);
+            total += weight;
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+
+        if total > 0.0 { acc / total } else { 0.0 }
+    }
+
+    fn turbulence(&self, p: &Point3, octaves: i32) -> f64 {
+        let mut acc = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            acc += weight * self.noise(&temp_p).abs();
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+
+        acc
+    }
+}
+
+#[inline(always)]
+pub fn solid(albedo: Colour) -> Arc<Texture> {
+    Arc::new(Texture::SolidColour(SolidColour { albedo }))
+}
+
+#[inline(always)]
+pub fn constant(x: f64) -> Arc<Texture> {
+    solid(colour(x, x, x))
+}
@@ -1,23 +1,39 @@
+use crate::vec3::*;
+use crate::ray::*;
 use std::f64::consts::PI;
-use std::f64::INFINITY;
 
 #[inline(always)]
 pub fn deg_to_rad(deg: f64) -> f64 {
     deg * PI / 180.0
 }
 
+// weight for a sample from a strategy with density `f_pdf`, when another
+// strategy with density `g_pdf` could also have produced it (Veach 1997, 9.2)
+#[inline(always)]
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0.0 { return 0.0; }
+    f / (f + g)
+}
+
+#[derive(Clone, Copy)]
 pub struct Interval {
     min: f64,
     max: f64
 }
 
 impl Interval {
-    pub fn all() -> Self {
-        Self { min: -INFINITY, max: INFINITY }
+    pub fn empty() -> Self {
+        Self { min: f64::INFINITY, max: -f64::INFINITY }
     }
 
     pub fn new(min: f64, max: f64) -> Self {
-        Self { min: min, max: max }
+        Self { min, max }
+    }
+
+    pub fn surrounding(a: &Interval, b: &Interval) -> Self {
+        Self { min: a.min.min(b.min), max: a.max.max(b.max) }
     }
 
     pub fn min(&self) -> f64 {
@@ -28,8 +44,13 @@ impl Interval {
         self.max
     }
 
-    pub fn has(&self, x: f64) -> bool {
-        x >= self.min && x <= self.max
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = 0.5 * delta;
+        Self { min: self.min - padding, max: self.max + padding }
     }
 
     pub fn interior(&self, x: f64) -> bool {
@@ -39,7 +60,7 @@ impl Interval {
     pub fn clamp(&self, x: f64) -> f64 {
         if x < self.min { return self.min; }
         if x > self.max { return self.max; }
-        return x;
+        x
     }
 
     // pub const EMPTY: Self = Self { max: -INFINITY, min: INFINITY };
@@ -48,4 +69,93 @@ impl Interval {
 
 pub fn interval(mi: f64, ma: f64) -> Interval {
     Interval { min: mi, max: ma }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    x: Interval,
+    y: Interval,
+    z: Interval
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self { x: Interval::empty(), y: Interval::empty(), z: Interval::empty() }
+    }
+
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        Self {
+            x: interval(a.x().min(b.x()), a.x().max(b.x())),
+            y: interval(a.y().min(b.y()), a.y().max(b.y())),
+            z: interval(a.z().min(b.z()), a.z().max(b.z()))
+        }.padded()
+    }
+
+    // flat boxes (axis aligned triangles) would never pass the slab test
+    fn padded(self) -> Self {
+        const DELTA: f64 = 1.0e-4;
+        let pad = |i: Interval| if i.size() < DELTA && i.size() >= 0.0 { i.expand(DELTA) } else { i };
+        Self { x: pad(self.x), y: pad(self.y), z: pad(self.z) }
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            x: Interval::surrounding(&a.x, &b.x),
+            y: Interval::surrounding(&a.y, &b.y),
+            z: Interval::surrounding(&a.z, &b.z)
+        }
+    }
+
+    pub fn axis(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() < 0.0 || self.y.size() < 0.0 || self.z.size() < 0.0
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        vec3(self.x.size(), self.y.size(), self.z.size())
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        point3(
+            0.5 * (self.x.min() + self.x.max()),
+            0.5 * (self.y.min() + self.y.max()),
+            0.5 * (self.z.min() + self.z.max())
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() { return 0.0; }
+        let d = self.diagonal();
+        2.0 * (d.x()*d.y() + d.y()*d.z() + d.z()*d.x())
+    }
+
+    // slab test, returns the part of `ray_t` spent inside the box
+    #[inline(always)]
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        let ori = r.ori();
+        let inv_dir = r.inv_dir();
+        let mut t_min = ray_t.min();
+        let mut t_max = ray_t.max();
+
+        for a in 0..3 {
+            let ax = self.axis(a);
+            let t0 = (ax.min() - ori[a]) * inv_dir[a];
+            let t1 = (ax.max() - ori[a]) * inv_dir[a];
+            // written so that a NaN (0 * inf) never narrows the interval
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if near > t_min { t_min = near; }
+            if far < t_max { t_max = far; }
+            if t_max < t_min { return None; }
+        }
+
+        Some(interval(t_min, t_max))
+    }
+}
@@ -1,8 +1,9 @@
-use std::{fs::File, ops, os::windows::thread};
+use std::{fs::File, ops};
 use std::io::Write;
-use rand::{thread_rng, Rng};
+use crate::sampler::*;
+use std::f64::consts::PI;
 use std::iter::Sum;
-use crate::Interval;
+use crate::util::Interval;
 
 #[derive(Copy, Clone)]
 pub struct Vec3 {
@@ -38,10 +39,29 @@ impl Vec3 {
         self.norm_sq().sqrt()
     }
 
+    pub fn max_component(&self) -> f64 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
+    // brightness of a linear rgb colour, rec. 709 weights
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
+
+    // linear rgb from cie xyz, with the srgb primaries and d65 white
+    pub fn xyz_to_rgb(&self) -> Colour {
+        let [x, y, z] = self.e;
+        colour(
+            3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+            0.0556434 * x - 0.2040259 * y + 1.0572252 * z
+        )
+    }
+
     pub fn near_zero(&self) -> bool {
         const S: f64 = 1.0e-8;
 
-        (self.e[0] < S) && (self.e[1] < S) && (self.e[2] < S)
+        (self.e[0].abs() < S) && (self.e[1].abs() < S) && (self.e[2].abs() < S)
     }
 }
 
@@ -223,14 +243,14 @@ pub fn write_colour(pixel_colour: Colour, write_out: &mut std::io::BufWriter<Fil
     let g = linear_to_gamma(pixel_colour.y());
     let b = linear_to_gamma(pixel_colour.z());
 
-    let INTENSITY: Interval = Interval::new(0.000, 0.999); // make a static?
-    // let rbyte = 256 * (INTENSITY.clamp(r)) as i32;
-    // let gbyte = 256 * (INTENSITY.clamp(g)) as i32;
-    // let bbyte = 256 * (INTENSITY.clamp(b)) as i32;
+    let intensity: Interval = Interval::new(0.000, 0.999); // make a static?
+    // let rbyte = 256 * (intensity.clamp(r)) as i32;
+    // let gbyte = 256 * (intensity.clamp(g)) as i32;
+    // let bbyte = 256 * (intensity.clamp(b)) as i32;
     // ^ this doesnt work for some reason, below does though
-    let rbyte = (255.999 * INTENSITY.clamp(r)) as i32;
-    let gbyte = (255.999 * INTENSITY.clamp(g)) as i32;
-    let bbyte = (255.999 * INTENSITY.clamp(b)) as i32;
+    let rbyte = (255.999 * intensity.clamp(r)) as i32;
+    let gbyte = (255.999 * intensity.clamp(g)) as i32;
+    let bbyte = (255.999 * intensity.clamp(b)) as i32;
 
     writeln!(write_out, "{} {} {}", rbyte, gbyte, bbyte).unwrap(); // use BufWrite
     // println!("{} {} {}", rbyte, gbyte, bbyte);
@@ -251,36 +271,41 @@ pub fn colour(a: impl Into<f64>, b: impl Into<f64>, c: impl Into<f64>) -> Colour
     Vec3::new(a, b, c)
 }
 
-pub fn randvec() -> Vec3 {
-    let mut rng = thread_rng();
-    let a: f64 = rng.gen();
-    let b: f64 = rng.gen();
-    let c: f64 = rng.gen();
-    vec3(a, b, c)
-}
-
-pub fn randvecr(min: f64, max: f64) -> Vec3 {
-    let mut rng = thread_rng();
-    let a: f64 = rng.gen_range(min..max);
-    let b: f64 = rng.gen_range(min..max);
-    let c: f64 = rng.gen_range(min..max);
-    vec3(a, b, c)
+// uniform on the surface of the unit sphere. mapped rather than rejected so
+// it always takes two numbers from the sampler
+#[inline(always)]
+pub fn randvec_in_unit_sphere(sampler: &mut Sampler) -> Vec3 {
+    let (u1, u2) = sampler.next_2d();
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z*z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    vec3(r * phi.cos(), r * phi.sin(), z)
 }
 
+// uniform over the directions within acos(cos_theta_max) of unit vector
+// `axis`, which covers 2 pi (1 - cos_theta_max) steradians
 #[inline(always)]
-pub fn randvec_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = randvecr(-1.0, 1.0);
-        if p.norm_sq() < 1.0 { return unit_vector(p); }
-    }
+pub fn randvec_in_cone(axis: &Vec3, cos_theta_max: f64, sampler: &mut Sampler) -> Vec3 {
+    let (r1, r2) = sampler.next_2d();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+    let phi = 2.0 * PI * r1;
+    let sin_theta = (1.0 - z*z).max(0.0).sqrt();
+
+    let (s, t) = coordinate_system(axis);
+    phi.cos() * sin_theta * s + phi.sin() * sin_theta * t + z * *axis
 }
 
+// two unit vectors completing an orthonormal basis with unit vector `n`
 #[inline(always)]
-pub fn randvec_on_hemisphere(normal: &Vec3) -> Vec3 {
-    // returns a random unit vector on unit sphere, such that it is
-    // in the hemisphere facing the source of rays
-    let rvec = randvec_in_unit_sphere();
-    return if dot(&rvec, normal) > 0.0 { rvec } else { -rvec };
+pub fn coordinate_system(n: &Vec3) -> (Vec3, Vec3) {
+    // Duff et al. 2017, branchless and continuous apart from the sign flip
+    let sign = 1.0f64.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    (
+        vec3(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+        vec3(b, sign + n.y() * n.y() * a, -n.y())
+    )
 }
 
 #[inline(always)]
@@ -299,10 +324,9 @@ pub fn refract(uv: &Vec3, n: &Vec3, frac_eta_i_eta_t: f64) -> Vec3 {
 }
 
 #[inline(always)]
-pub fn randvec_in_unit_disc() -> Vec3 {
-    loop {
-        let mut rng = thread_rng();
-        let p = vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0);
-        if p.norm_sq() < 1.0 { return p; }
-    }
+pub fn randvec_in_unit_disc(sampler: &mut Sampler) -> Vec3 {
+    let (u1, u2) = sampler.next_2d();
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    vec3(r * phi.cos(), r * phi.sin(), 0)
 }
\ No newline at end of file
@@ -0,0 +1,289 @@
+use crate::vec3::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+// edge length of the bricks sparse grids are stored in, as with openvdb's
+// leaf nodes
+const BRICK: usize = 8;
+
+// scalar values at the centres of the cells of a regular grid, e.g. smoke
+// density from a simulation cache. lookups are in the unit cube
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    data: VoxelData
+}
+
+enum VoxelData {
+    Dense(Vec<f32>), // x fastest, then y, then z
+    Sparse(HashMap<(usize, usize, usize), Box<[f32]>>) // bricks that are all 0 are left out
+}
+
+// the largest value a lookup can return anywhere inside each cell of a
+// coarser grid, for skipping empty space when tracking through a volume
+pub struct MajorantGrid {
+    res: usize,
+    max: Vec<f64>
+}
+
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>) -> io::Result<Self> {
+        if values.len() != voxel_count(nx, ny, nz)? {
+            return Err(Error::new(ErrorKind::InvalidData, "voxel count doesn't match the grid size"));
+        }
+        Ok(Self { nx, ny, nz, data: VoxelData::Dense(values) })
+    }
+
+    // headerless little endian f32s, x fastest, then y, then z
+    pub fn load_raw(path: impl AsRef<Path>, nx: usize, ny: usize, nz: usize) -> io::Result<Self> {
+        let count = voxel_count(nx, ny, nz)?;
+        let bytes = fs::read(path)?;
+        if count.checked_mul(4) != Some(bytes.len()) {
+            return Err(Error::new(ErrorKind::InvalidData, "raw grid size doesn't match its dimensions"));
+        }
+        Self::new(nx, ny, nz, read_f32s(&bytes))
+    }
+
+    // mitsuba's .vol format: "VOL", version 3, then i32 encoding (1 for
+    // f32), resolution in x, y and z, channel count, and an f32 bounding box
+    // before the data. only the first channel is kept, and the box is left
+    // to whoever places the volume
+    pub fn load_vol(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 { return Err(invalid("not a version 3 .vol file")); }
+
+        let int = |i: usize| i32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if int(4) != 1 { return Err(invalid("only f32 .vol grids are supported")); }
+        let size = |i: usize| usize::try_from(int(i)).map_err(|_| invalid("negative .vol grid size"));
+        let (nx, ny, nz, channels) = (size(8)?, size(12)?, size(16)?, size(20)?);
+        if channels == 0 { return Err(invalid(".vol grid has no channels")); }
+
+        let values = read_f32s(&bytes[48..]);
+        if voxel_count(nx, ny, nz)?.checked_mul(channels) != Some(values.len()) { return Err(invalid("truncated .vol file")); }
+        Self::new(nx, ny, nz, values.into_iter().step_by(channels).collect())
+    }
+
+    // keeps only the bricks with something in them, which is most of the
+    // saving for smoke and clouds that fill little of their box
+    pub fn sparse(self) -> Self {
+        let VoxelData::Dense(values) = &self.data else { return self; };
+        let mut bricks = HashMap::new();
+
+        for bz in 0..self.nz.div_ceil(BRICK) {
+            for by in 0..self.ny.div_ceil(BRICK) {
+                for bx in 0..self.nx.div_ceil(BRICK) {
+                    let mut brick = vec![0.0f32; BRICK * BRICK * BRICK];
+                    let mut empty = true;
+                    for (n, v) in brick.iter_mut().enumerate() {
+                        let (i, j, k) = (bx * BRICK + n % BRICK, by * BRICK + (n / BRICK) % BRICK, bz * BRICK + n / (BRICK * BRICK));
+                        if i >= self.nx || j >= self.ny || k >= self.nz { continue; }
+                        *v = values[(k * self.ny + j) * self.nx + i];
+                        empty &= *v == 0.0;
+                    }
+                    if !empty { bricks.insert((bx, by, bz), brick.into_boxed_slice()); }
+                }
+            }
+        }
+
+        Self { data: VoxelData::Sparse(bricks), ..self }
+    }
+
+    // the value stored for cell (i, j, k)
+    pub fn get(&self, i: usize, j: usize, k: usize) -> f64 {
+        match &self.data {
+            VoxelData::Dense(values) => values[(k * self.ny + j) * self.nx + i] as f64,
+            VoxelData::Sparse(bricks) => match bricks.get(&(i / BRICK, j / BRICK, k / BRICK)) {
+                Some(brick) => brick[((k % BRICK) * BRICK + j % BRICK) * BRICK + i % BRICK] as f64,
+                None => 0.0
+            }
+        }
+    }
+
+    // trilinear between cell centres, at a point in the unit cube. the
+    // outermost cells carry on to the edges
+    pub fn lookup(&self, p: &Point3) -> f64 {
+        let (i0, fx) = split(p.x(), self.nx);
+        let (j0, fy) = split(p.y(), self.ny);
+        let (k0, fz) = split(p.z(), self.nz);
+        let (i1, j1, k1) = ((i0 + 1).min(self.nx - 1), (j0 + 1).min(self.ny - 1), (k0 + 1).min(self.nz - 1));
+
+        let lerp = |a: f64, b: f64, t: f64| a + t * (b - a);
+        let y0 = lerp(lerp(self.get(i0, j0, k0), self.get(i1, j0, k0), fx), lerp(self.get(i0, j1, k0), self.get(i1, j1, k0), fx), fy);
+        let y1 = lerp(lerp(self.get(i0, j0, k1), self.get(i1, j0, k1), fx), lerp(self.get(i0, j1, k1), self.get(i1, j1, k1), fx), fy);
+        lerp(y0, y1, fz)
+    }
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &VoxelGrid, res: usize) -> Self {
+        let res = res.max(1);
+        let mut max = vec![0.0; res * res * res];
+
+        // cells of the voxel grid that lookups inside [c, c + 1) / res can
+        // blend between
+        let span = |c: usize, n: usize| {
+            let lo = ((c as f64 / res as f64) * n as f64 - 0.5).floor().max(0.0) as usize;
+            let hi = (((c + 1) as f64 / res as f64) * n as f64 - 0.5).floor().max(0.0) as usize + 1;
+            lo.min(n - 1)..=hi.min(n - 1)
+        };
+
+        for z in 0..res {
+            for y in 0..res {
+                for x in 0..res {
+                    let mut m: f64 = 0.0;
+                    for k in span(z, grid.nz) {
+                        for j in span(y, grid.ny) {
+                            for i in span(x, grid.nx) { m = m.max(grid.get(i, j, k)); }
+                        }
+                    }
+                    max[(z * res + y) * res + x] = m;
+                }
+            }
+        }
+
+        Self { res, max }
+    }
+
+    // walks the cells the unit cube space ray o + t d crosses between t0 and
+    // t1 in order, calling f(t_enter, t_exit, majorant) until it returns false
+    pub fn march<F: FnMut(f64, f64, f64) -> bool>(&self, o: &Point3, d: &Vec3, t0: f64, t1: f64, mut f: F) {
+        let res = self.res as f64;
+        let start = *o + t0 * *d;
+        let mut cell = [0i64; 3];
+        let mut step = [0i64; 3];
+        let mut next_t = [f64::INFINITY; 3];
+        let mut delta_t = [f64::INFINITY; 3];
+
+        for a in 0..3 {
+            cell[a] = ((start[a] * res).floor() as i64).clamp(0, self.res as i64 - 1);
+            if d[a] == 0.0 { continue; }
+
+            step[a] = if d[a] > 0.0 { 1 } else { -1 };
+            let boundary = (cell[a] + i64::from(d[a] > 0.0)) as f64 / res;
+            next_t[a] = (boundary - o[a]) / d[a];
+            delta_t[a] = 1.0 / (res * d[a].abs());
+        }
+
+        let mut t = t0;
+        loop {
+            let axis = if next_t[0] < next_t[1] && next_t[0] < next_t[2] { 0 } else if next_t[1] < next_t[2] { 1 } else { 2 };
+            let t_exit = next_t[axis].min(t1);
+            let idx = ((cell[2] * self.res as i64 + cell[1]) * self.res as i64 + cell[0]) as usize;
+            if !f(t, t_exit, self.max[idx]) || t_exit >= t1 { return; }
+
+            t = t_exit;
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.res as i64 { return; }
+            next_t[axis] += delta_t[axis];
+        }
+    }
+}
+
+// lower cell index and blend weight for a unit cube coordinate, with cell
+// centres at (i + 0.5) / n
+#[inline(always)]
+fn split(x: f64, n: usize) -> (usize, f64) {
+    let g = (x * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+    let i = (g.floor() as usize).min(n - 1);
+    (i, g - i as f64)
+}
+
+// nx * ny * nz, refusing grids that are empty or too big to index
+fn voxel_count(nx: usize, ny: usize, nz: usize) -> io::Result<usize> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    if nx == 0 || ny == 0 || nz == 0 { return Err(invalid("grid has no voxels")); }
+    nx.checked_mul(ny).and_then(|n| n.checked_mul(nz)).ok_or_else(|| invalid("grid is too big"))
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a .vol file of the given resolution and channel count, holding the
+    // values 0, 1, 2, ... in order
+    fn vol_bytes(nx: i32, ny: i32, nz: i32, channels: i32) -> Vec<u8> {
+        let mut bytes = b"VOL".to_vec();
+        bytes.push(3);
+        for v in [1, nx, ny, nz, channels] { bytes.extend(v.to_le_bytes()); }
+        for v in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0] { bytes.extend(v.to_le_bytes()); }
+        for n in 0..nx * ny * nz * channels { bytes.extend((n as f32).to_le_bytes()); }
+        bytes
+    }
+
+    fn load(name: &str, bytes: &[u8]) -> io::Result<VoxelGrid> {
+        let path = std::env::temp_dir().join(format!("pbptr-{}-{name}.vol", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let grid = VoxelGrid::load_vol(&path);
+        fs::remove_file(&path).unwrap();
+        grid
+    }
+
+    #[test]
+    fn loads_vol_keeping_the_first_channel() {
+        let grid = load("two-channels", &vol_bytes(3, 2, 4, 2)).unwrap();
+        assert_eq!((grid.nx, grid.ny, grid.nz), (3, 2, 4));
+        for k in 0..4 {
+            for j in 0..2 {
+                for i in 0..3 {
+                    assert_eq!(grid.get(i, j, k), (2 * ((k * 2 + j) * 3 + i)) as f64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        let mut version = vol_bytes(2, 2, 2, 1);
+        version[3] = 2;
+        let mut encoding = vol_bytes(2, 2, 2, 1);
+        encoding[4] = 2;
+        let mut truncated = vol_bytes(2, 2, 2, 1);
+        truncated.truncate(truncated.len() - 4);
+        let mut overflowing = vol_bytes(1, 1, 1, 1);
+        for i in [8, 12, 16] { overflowing[i..i + 4].copy_from_slice(&i32::MAX.to_le_bytes()); }
+
+        let cases = [
+            ("version", version),
+            ("encoding", encoding),
+            ("truncated", truncated),
+            ("empty", Vec::new()),
+            ("zero-size", vol_bytes(2, 0, 2, 1)),
+            ("negative-size", vol_bytes(2, -2, 2, 1)),
+            ("overflowing", overflowing)
+        ];
+        for (name, bytes) in cases {
+            let err = load(name, &bytes).err().unwrap_or_else(|| panic!("{name} loaded"));
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        let path = std::env::temp_dir().join(format!("pbptr-{}-empty.raw", std::process::id()));
+        fs::write(&path, []).unwrap();
+        let raw = VoxelGrid::load_raw(&path, 0, 0, 0);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(raw.err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+        assert_eq!(VoxelGrid::new(0, 0, 0, Vec::new()).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn sparse_matches_dense() {
+        let (nx, ny, nz) = (11, 9, 17);
+        let values: Vec<f32> = (0..nx * ny * nz).map(|n| if n % 97 < 5 { n as f32 } else { 0.0 }).collect();
+        let dense = VoxelGrid::new(nx, ny, nz, values.clone()).unwrap();
+        let sparse = VoxelGrid::new(nx, ny, nz, values).unwrap().sparse();
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx { assert_eq!(dense.get(i, j, k), sparse.get(i, j, k)); }
+            }
+        }
+        let p = point3(0.31, 0.77, 0.52);
+        assert_eq!(dense.lookup(&p), sparse.lookup(&p));
+    }
+}